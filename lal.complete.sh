@@ -8,11 +8,11 @@ _lal()
     local -r subcommands="build clean configure export fetch help init script run ls
                           query remove rm shell stash save status update upgrade verify
                           publish env list-components list-supported-environments list-dependencies
-                          list-environments list-configurations propagate"
+                          list-environments list-configurations propagate cache"
 
     local has_sub
     for (( i=0; i < ${#words[@]}-1; i++ )); do
-        if [[ ${words[i]} == @(build|clean|configure|export|script|propagate|fetch|help|init|remove|rm|script|run|query|shell|stash|save|status|ls|update|upgrade|verify|publish|env) ]]; then
+        if [[ ${words[i]} == @(build|clean|configure|export|script|propagate|fetch|help|init|remove|rm|script|run|query|shell|stash|save|status|ls|update|upgrade|verify|publish|env|cache) ]]; then
             has_sub=1
         fi
     done
@@ -24,7 +24,7 @@ _lal()
 
     # global flags
     if [[ $prev = 'lal' && "$cur" == -* ]]; then
-        COMPREPLY=( $(compgen -W '-v -h -V --version --help' -- "$cur" ) )
+        COMPREPLY=( $(compgen -W '-v -h -V --version --help --offline' -- "$cur" ) )
         return 0
     fi
     # first subcommand
@@ -36,7 +36,7 @@ _lal()
     # special subcommand completions
     local special i
     for (( i=0; i < ${#words[@]}-1; i++ )); do
-        if [[ ${words[i]} == @(build|remove|rm|propagate|export|init|update|script|run|status|ls|query|shell|publish|env|configure|help) ]]; then
+        if [[ ${words[i]} == @(build|remove|rm|propagate|export|init|update|script|run|status|ls|query|shell|publish|env|cache|configure|help) ]]; then
             special=${words[i]}
         fi
     done
@@ -83,6 +83,12 @@ _lal()
                     COMPREPLY=($(compgen -W "$env_subs" -- "$cur"))
                 fi
                 ;;
+            cache)
+                if [[ $prev = "cache" ]]; then
+                    local -r cache_subs="verify ls rm import path help -h --help"
+                    COMPREPLY=($(compgen -W "$cache_subs" -- "$cur"))
+                fi
+                ;;
             init)
                 if [[ $prev = "init" ]]; then
                     local -r envs="$(lal list-environments)"
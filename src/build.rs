@@ -1,10 +1,16 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::env;
+use std::sync::Mutex;
+
+use ansi_term::Colour;
+use crossbeam;
 
 use shell;
+use buildcache;
 use verify::verify;
-use super::{ensure_dir_exists_fresh, output, Lockfile, Manifest, Container, Config, LalResult,
-            CliError, DockerRunFlags, ShellModes};
+use super::{ensure_dir_exists_fresh, output, Lockfile, Manifest, Container, Config, Mount,
+            LalResult, CliError, DockerRunFlags, ShellModes};
 
 
 fn find_valid_build_script() -> LalResult<String> {
@@ -56,23 +62,13 @@ pub struct BuildOptions {
     pub force: bool,
     /// Use the `simple` verify algorithm
     pub simple_verify: bool,
+    /// Restore `OUTPUT` from the build cache when nothing changed since a cached build
+    pub cache: bool,
 }
 
 
-/// Runs the `./BUILD` script in a container and packages artifacts.
-///
-/// The function performs basic sanity checks, before shelling out to `docker run`
-/// to perform the actual execution of the containerized `./BUILD` script.
-///
-pub fn build(
-    cfg: &Config,
-    manifest: &Manifest,
-    opts: &BuildOptions,
-    envname: String,
-    _modes: ShellModes,
-) -> LalResult<()> {
-    let mut modes = _modes;
-
+// clean out OUTPUT and verify INPUT, returning whether verify failed (with force)
+fn prepare(manifest: &Manifest, opts: &BuildOptions, envname: &str) -> LalResult<bool> {
     // have a better warning on first file-io operation
     // if nfs mounts and stuff cause issues this usually catches it
     ensure_dir_exists_fresh("./OUTPUT")
@@ -84,15 +80,46 @@ pub fn build(
     debug!("Version flag is {:?}", opts.version);
 
     // Verify INPUT
-    let mut verify_failed = false;
-    if let Some(e) = verify(manifest, &envname, opts.simple_verify).err() {
+    if let Some(e) = verify(manifest, envname, opts.simple_verify).err() {
         if !opts.force {
             return Err(e);
         }
-        verify_failed = true;
         warn!("Verify failed - build will fail on jenkins, but continuing");
+        return Ok(true);
     }
+    Ok(false)
+}
 
+// record (and use) exactly what the tag points at
+fn resolve_container(
+    cfg: &Config,
+    opts: &BuildOptions,
+    modes: &ShellModes,
+) -> LalResult<Container> {
+    if modes.native || modes.printonly {
+        Ok(opts.container.clone())
+    } else {
+        shell::resolve_digest(cfg.runtime, &opts.container)
+    }
+}
+
+/// Runs the `./BUILD` script in a container and packages artifacts.
+///
+/// The function performs basic sanity checks, before shelling out to `docker run`
+/// to perform the actual execution of the containerized `./BUILD` script.
+/// With `modes.native` the script runs on the host, and the lockfile records this.
+/// With `opts.cache`, a build with unchanged inputs is restored from the build cache
+/// instead of running the script again.
+///
+pub fn build(
+    cfg: &Config,
+    manifest: &Manifest,
+    opts: &BuildOptions,
+    envname: String,
+    _modes: ShellModes,
+) -> LalResult<()> {
+    let mut modes = _modes;
+    let verify_failed = prepare(manifest, opts, &envname)?;
 
     let component = opts.name.clone().unwrap_or_else(|| manifest.name.clone());
     debug!("Getting configurations for {}", component);
@@ -114,37 +141,61 @@ pub fn build(
         let ename = format!("{} not found in configurations list", configuration_name);
         return Err(CliError::InvalidBuildConfiguration(ename));
     }
+    let container = resolve_container(cfg, opts, &modes)?;
     let lockfile = Lockfile::new(&component,
-                                 &opts.container,
+                                 &container,
                                  &envname,
                                  opts.version.clone(),
                                  Some(&configuration_name))
         .set_default_env(manifest.environment.clone())
         .attach_revision_id(opts.sha.clone())
+        .set_native(modes.native)
         .populate_from_input()?;
 
     let lockpth = Path::new("./OUTPUT/lockfile.json");
     lockfile.write(lockpth)?; // always put a lockfile in OUTPUT at the start of a build
 
     let bpath = find_valid_build_script()?;
-    let cmd = vec![bpath, component.clone(), configuration_name];
+    let cmd = vec![bpath, component.clone(), configuration_name.clone()];
 
     if let Some(v) = opts.version.clone() {
         modes.env_vars.push(format!("BUILD_VERSION={}", v));
     }
 
-    debug!("Build script is {:?}", cmd);
-    if !modes.printonly {
-        info!("Running build script in {} container", envname);
-    }
-
-    let run_flags = DockerRunFlags {
-        interactive: cfg.interactive,
-        privileged: false,
+    let key = if opts.cache && !modes.printonly {
+        Some(buildcache::build_key(&component, &configuration_name, &envname, &container, &modes)?)
+    } else {
+        None
     };
-    shell::docker_run(cfg, &opts.container, cmd, &run_flags, &modes)?;
-    if modes.printonly {
-        return Ok(()); // nothing else worth doing - warnings are pointless
+    let restored = match key {
+        Some(ref k) => buildcache::restore(&cfg.cache, &component, k, Path::new("OUTPUT"))?,
+        None => false,
+    };
+
+    if restored {
+        info!("Inputs unchanged - restored OUTPUT from the build cache");
+        lockfile.write(lockpth)?; // the cached lockfile is from the earlier build
+    } else {
+        debug!("Build script is {:?}", cmd);
+        if modes.native {
+            warn!("Running build script natively on the host as the {} environment", envname);
+        } else if !modes.printonly {
+            info!("Running build script in {} container", envname);
+        }
+
+        let run_flags = DockerRunFlags {
+            interactive: cfg.interactive,
+            privileged: false,
+        };
+        shell::docker_run(cfg, &container, cmd, &run_flags, &modes)?;
+        if modes.printonly {
+            return Ok(()); // nothing else worth doing - warnings are pointless
+        }
+        if let Some(ref k) = key {
+            if let Err(e) = buildcache::store(&cfg.cache, &component, k, Path::new("OUTPUT")) {
+                warn!("Failed to save the build in the build cache: {}", e);
+            }
+        }
     }
 
     // Extra info and warnings for people who missed the leading ones (build is spammy)
@@ -171,3 +222,197 @@ pub fn build(
     }
     Ok(())
 }
+
+/// Component and configuration pairs built by `lal build --all`
+///
+/// With a component, these are all of its configurations, otherwise all the
+/// configurations of every component in the manifest.
+pub fn build_combinations(
+    manifest: &Manifest,
+    component: Option<&str>,
+) -> LalResult<Vec<(String, String)>> {
+    let mut res = vec![];
+    for (name, settings) in &manifest.components {
+        if component.map_or(true, |c| c == name) {
+            for configuration in &settings.configurations {
+                res.push((name.clone(), configuration.clone()));
+            }
+        }
+    }
+    match component {
+        Some(c) if res.is_empty() => Err(CliError::MissingComponent(c.into())),
+        _ => Ok(res),
+    }
+}
+
+// a component and configuration built by `build_all`
+struct Combination {
+    component: String,
+    title: String,
+    cfg: Config,
+    cmd: Vec<String>,
+    output: PathBuf,
+    key: Option<String>,
+    restored: bool,
+}
+
+/// Runs the `./BUILD` script for several components and configurations
+///
+/// Every combination builds into its own `OUTPUT/<component>/<configuration>/`
+/// with its own lockfile, which is mounted as `OUTPUT` in its container.
+/// Up to `jobs` combinations are built in parallel containers, and the result of every
+/// combination is summarized at the end. Release builds and native builds are
+/// not supported, as these need a single `OUTPUT`.
+/// With `opts.cache`, each combination is restored from the build cache when its
+/// inputs are unchanged.
+pub fn build_all(
+    cfg: &Config,
+    manifest: &Manifest,
+    opts: &BuildOptions,
+    combinations: &[(String, String)],
+    envname: String,
+    _modes: ShellModes,
+    jobs: usize,
+) -> LalResult<()> {
+    let mut modes = _modes;
+    let verify_failed = prepare(manifest, opts, &envname)?;
+
+    for &(ref component, ref configuration) in combinations {
+        let settings = match manifest.components.get(component) {
+            Some(c) => c,
+            None => return Err(CliError::MissingComponent(component.clone())),
+        };
+        if !settings.configurations.contains(configuration) {
+            let ename = format!("{} not found in configurations list", configuration);
+            return Err(CliError::InvalidBuildConfiguration(ename));
+        }
+    }
+    let container = resolve_container(cfg, opts, &modes)?;
+    let bpath = find_valid_build_script()?;
+    if let Some(v) = opts.version.clone() {
+        modes.env_vars.push(format!("BUILD_VERSION={}", v));
+    }
+
+    // every combination gets its own OUTPUT directory and lockfile
+    let pwd = env::current_dir()?;
+    // the sources are shared, so only hash them once
+    let sources = if opts.cache && !modes.printonly {
+        Some(buildcache::sources_hash()?)
+    } else {
+        None
+    };
+    let mut builds = vec![];
+    for &(ref component, ref configuration) in combinations {
+        let outdir = Path::new("OUTPUT").join(component).join(configuration);
+        fs::create_dir_all(&outdir)?;
+        let lockfile = Lockfile::new(component,
+                                     &container,
+                                     &envname,
+                                     opts.version.clone(),
+                                     Some(configuration))
+            .set_default_env(manifest.environment.clone())
+            .attach_revision_id(opts.sha.clone())
+            .populate_from_input()?;
+        lockfile.write(&outdir.join("lockfile.json"))?;
+        let title = format!("{} ({})", component, configuration);
+
+        let key = match sources {
+            Some(ref s) => {
+                Some(buildcache::combination_key(s, component, configuration, &envname,
+                                                 &container, &modes)?)
+            }
+            None => None,
+        };
+        let restored = match key {
+            Some(ref k) => buildcache::restore(&cfg.cache, component, k, &outdir)?,
+            None => false,
+        };
+        if restored {
+            info!("Inputs of {} unchanged - restored from the build cache", title);
+            lockfile.write(&outdir.join("lockfile.json"))?;
+        }
+
+        let mut build_cfg = cfg.clone();
+        build_cfg.mounts.push(Mount {
+            src: pwd.join(&outdir).display().to_string(),
+            dest: "/home/lal/volume/OUTPUT".into(),
+            readonly: false,
+        });
+        builds.push(Combination {
+            component: component.clone(),
+            title: title,
+            cfg: build_cfg,
+            cmd: vec![bpath.clone(), component.clone(), configuration.clone()],
+            output: outdir,
+            key: key,
+            restored: restored,
+        });
+    }
+
+    // parallel builds cannot share the terminal
+    let run_flags = DockerRunFlags {
+        interactive: cfg.interactive && jobs <= 1,
+        privileged: false,
+    };
+    let queue = Mutex::new(builds.iter().enumerate().filter(|&(_, b)| !b.restored));
+    let results = Mutex::new(builds.iter()
+                                 .map(|b| if b.restored { Some(Ok(())) } else { None })
+                                 .collect::<Vec<_>>());
+    crossbeam::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            let queue = &queue;
+            let results = &results;
+            let envname = &envname;
+            let container = &container;
+            let modes = &modes;
+            let run_flags = &run_flags;
+            let cache = &cfg.cache;
+            scope.spawn(move || loop {
+                let next = queue.lock().unwrap().next();
+                let (i, b) = match next {
+                    Some(n) => n,
+                    None => break,
+                };
+                if !modes.printonly {
+                    info!("Building {} in {} container", b.title, envname);
+                }
+                let res = shell::docker_run(&b.cfg, container, b.cmd.clone(), run_flags, modes);
+                if let (&Ok(_), &Some(ref k)) = (&res, &b.key) {
+                    if let Err(e) = buildcache::store(cache, &b.component, k, &b.output) {
+                        warn!("Failed to save {} in the build cache: {}", b.title, e);
+                    }
+                }
+                results.lock().unwrap()[i] = Some(res);
+            });
+        }
+    });
+    if modes.printonly {
+        return Ok(()); // nothing was built
+    }
+
+    // every slot is filled once the scope has joined all the threads
+    let results: Vec<LalResult<()>> =
+        results.into_inner().unwrap().into_iter().map(|r| r.unwrap()).collect();
+    let mut failed = 0;
+    println!("Build summary:");
+    for (b, res) in builds.iter().zip(results) {
+        let cached = if b.restored { " (cached)" } else { "" };
+        match res {
+            Ok(_) => println!("  {} {}{}", Colour::Green.paint("ok    "), b.title, cached),
+            Err(e) => {
+                failed += 1;
+                println!("  {} {}: {}", Colour::Red.paint("failed"), b.title, e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(CliError::BuildFailures(failed, builds.len()));
+    }
+    if verify_failed {
+        warn!("Builds succeeded - but `lal verify` failed");
+        warn!("Please make sure you are using correct dependencies before pushing")
+    } else {
+        info!("Builds succeeded with verified dependencies")
+    }
+    Ok(())
+}
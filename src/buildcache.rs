@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use filetime::{FileTime, set_file_times};
+use openssl::error::ErrorStack;
+use openssl::hash::{Hasher, MessageDigest};
+use walkdir::WalkDir;
+
+use super::{CliError, LalResult, Container, ShellModes, Checksum, HashType, input, output,
+            ensure_dir_exists_fresh, to_hex};
+
+// directories that are never part of the sources of a build
+const GENERATED: [&'static str; 4] = [".git", "INPUT", "OUTPUT", "ARTIFACT"];
+
+fn is_generated(pth: &Path) -> bool {
+    pth.components()
+        .next()
+        .map_or(false, |c| GENERATED.iter().any(|g| c.as_os_str() == *g))
+}
+
+// files making up the sources: git tracked and untracked non-ignored files, else all files
+fn source_files() -> LalResult<Vec<PathBuf>> {
+    let git = Command::new("git")
+        .args(&["ls-files", "-z", "--cached", "--others", "--exclude-standard"])
+        .output();
+    let mut files: Vec<PathBuf> = match git {
+        Ok(ref o) if o.status.success() => {
+            String::from_utf8_lossy(&o.stdout)
+                .split('\0')
+                .filter(|f| !f.is_empty())
+                .map(PathBuf::from)
+                .collect()
+        }
+        _ => {
+            debug!("Not in a git repository - hashing all files");
+            WalkDir::new(".")
+                .into_iter()
+                .filter_entry(|e| e.depth() != 1 || !GENERATED.iter().any(|g| e.file_name() == *g))
+                .filter_map(|e| e.ok())
+                .filter(|e| !e.file_type().is_dir())
+                .filter_map(|e| e.path().strip_prefix(".").ok().map(|p| p.to_path_buf()))
+                .collect()
+        }
+    };
+    files.retain(|f| !is_generated(f));
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+// commits recorded for git submodules, which git lists as a single (directory) path
+fn gitlinks() -> BTreeMap<PathBuf, String> {
+    let git = Command::new("git").args(&["ls-files", "-z", "--stage"]).output();
+    let mut links = BTreeMap::new();
+    if let Ok(ref o) = git {
+        if o.status.success() {
+            // entries are "<mode> <sha> <stage>\t<path>"
+            for entry in String::from_utf8_lossy(&o.stdout).split('\0') {
+                let parts: Vec<&str> = entry.splitn(2, '\t').collect();
+                let info: Vec<&str> = parts[0].split(' ').collect();
+                if parts.len() == 2 && info.len() == 3 && info[0] == "160000" {
+                    links.insert(PathBuf::from(parts[1]), info[1].to_string());
+                }
+            }
+        }
+    }
+    links
+}
+
+// hash of a file's contents, of the target of a symlink, or the commit of a submodule
+fn hash_file(pth: &Path, links: &BTreeMap<PathBuf, String>) -> LalResult<Option<String>> {
+    let meta = match fs::symlink_metadata(pth) {
+        Ok(m) => m,
+        Err(_) => return Ok(None), // deleted, but still tracked
+    };
+    if meta.file_type().is_symlink() {
+        return Ok(Some(format!("-> {}", fs::read_link(pth)?.display())));
+    }
+    if meta.is_dir() {
+        return Ok(links.get(pth).map(|sha| format!("commit {}", sha)));
+    }
+    Ok(Some(Checksum::of_file(HashType::Sha256, pth)?.hex))
+}
+
+// sha256 of the lines describing (part of) a build
+fn hash_desc(desc: &[String]) -> LalResult<String> {
+    let herr = |e: ErrorStack| CliError::BackendFailure(format!("hashing failed: {}", e));
+    let mut hasher = Hasher::new(MessageDigest::sha256()).map_err(&herr)?;
+    hasher.update(desc.join("\n").as_bytes()).map_err(&herr)?;
+    Ok(to_hex(&hasher.finish().map_err(&herr)?))
+}
+
+/// Hash the sources of the build in the current directory
+///
+/// This covers the lockfiles in `./INPUT` and the contents of all the sources
+/// (files known to git, or all files outside a git repository).
+/// Git submodules are covered by the commit recorded for them.
+/// It is the part of the key shared by every combination built from the same directory.
+pub fn sources_hash() -> LalResult<String> {
+    let mut desc = vec![];
+    let links = gitlinks();
+    if input::present() {
+        for name in input::analyze()?.keys() {
+            let lock_path = Path::new("./INPUT").join(name).join("lockfile.json");
+            let hash = hash_file(&lock_path, &links)?.unwrap_or_else(|| "-".into());
+            desc.push(format!("input {} {}", name, hash));
+        }
+    }
+    for f in source_files()? {
+        if let Some(hash) = hash_file(&f, &links)? {
+            desc.push(format!("source {} {}", f.display(), hash));
+        }
+    }
+    trace!("Sources of the build:\n{}", desc.join("\n"));
+    hash_desc(&desc)
+}
+
+/// Compute the key identifying a build from the hash of its sources
+///
+/// The key covers the `sources_hash` together with the component and configuration,
+/// the environment and container (with its digest when known), the variables passed
+/// to the build (like `BUILD_VERSION`), and whether the build is native.
+pub fn combination_key(
+    sources: &str,
+    component: &str,
+    configuration: &str,
+    env: &str,
+    container: &Container,
+    modes: &ShellModes,
+) -> LalResult<String> {
+    let mut desc = vec![
+        format!("component {}", component),
+        format!("configuration {}", configuration),
+        format!("environment {}", env),
+        format!("container {}", container),
+        format!("native {}", modes.native),
+    ];
+    for var in &modes.env_vars {
+        desc.push(format!("env {}", var));
+    }
+    desc.push(format!("sources {}", sources));
+    trace!("Build key for:\n{}", desc.join("\n"));
+    hash_desc(&desc)
+}
+
+/// Compute the key identifying a build in the current directory
+///
+/// This is the `combination_key` for the current `sources_hash`.
+pub fn build_key(
+    component: &str,
+    configuration: &str,
+    env: &str,
+    container: &Container,
+    modes: &ShellModes,
+) -> LalResult<String> {
+    combination_key(&sources_hash()?, component, configuration, env, container, modes)
+}
+
+fn cached_build_dir(cache: &str, component: &str, key: &str) -> PathBuf {
+    Path::new(cache).join("builds").join(component).join(key)
+}
+
+/// Restore an output directory (normally `OUTPUT`) from the build cache
+///
+/// Returns whether a build with the key was found in the cache.
+pub fn restore(cache: &str, component: &str, key: &str, output: &Path) -> LalResult<bool> {
+    use tar::Archive;
+    use flate2::read::GzDecoder;
+
+    let dir = cached_build_dir(cache, component, key);
+    let tarpath = dir.join(format!("{}.tar.gz", component));
+    if !tarpath.is_file() {
+        debug!("No cached build of {} for {}", component, key);
+        return Ok(false);
+    }
+    debug!("Restoring {} from {}", output.display(), tarpath.display());
+    ensure_dir_exists_fresh(&output.to_string_lossy())?;
+    let data = fs::File::open(&tarpath)?;
+    let mut archive = Archive::new(GzDecoder::new(data)?);
+    archive.unpack(output)?;
+
+    // record the use for `lal clean`
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    let time = FileTime::from_seconds_since_1970(now.as_secs(), now.subsec_nanos());
+    if let Err(e) = set_file_times(&dir, time, time) {
+        debug!("Failed to update last use of {}: {}", dir.display(), e);
+    }
+    Ok(true)
+}
+
+/// Save an output directory (normally `OUTPUT`) in the build cache
+///
+/// The tarball is written to the `partial` tree and then moved into place,
+/// so an interrupted save never leaves a truncated build in the cache.
+pub fn store(cache: &str, component: &str, key: &str, output: &Path) -> LalResult<()> {
+    let dir = cached_build_dir(cache, component, key);
+    if dir.is_dir() {
+        return Ok(()); // an identical build is already cached
+    }
+    let partial = Path::new(cache).join("partial").join("builds").join(component).join(key);
+    ensure_dir_exists_fresh(&partial.to_string_lossy())?;
+    output::tar_dir(&partial.join(format!("{}.tar.gz", component)), output)?;
+    fs::create_dir_all(dir.parent().unwrap())?;
+    fs::rename(&partial, &dir)?;
+    debug!("Cached {} of {} as {}", output.display(), component, key);
+    Ok(())
+}
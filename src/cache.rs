@@ -0,0 +1,370 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, UTC};
+use serde_json;
+use walkdir::WalkDir;
+
+use super::{CliError, LalResult, Checksum, Version, cached_tarball, import_tarball};
+use super::clean::{dir_size, format_size, mtime_of, remove_unused_entry};
+
+// all cached tarballs as (tarball, containing directory) pairs
+fn cached_tarballs(cachedir: &str) -> Vec<(PathBuf, PathBuf)> {
+    // environments/$env/$name/$version/$name.tar.gz and stash/$name/$code/$name.tar.gz
+    let trees = [("environments", 4), ("stash", 3)];
+    let mut res = vec![];
+    for &(tree, depth) in &trees {
+        let dir = Path::new(cachedir).join(tree);
+        let entries = WalkDir::new(&dir).min_depth(depth).max_depth(depth);
+        for e in entries.into_iter().filter_map(|e| e.ok()) {
+            let pth = e.path();
+            if pth.is_file() && pth.to_string_lossy().ends_with(".tar.gz") {
+                if let Some(parent) = pth.parent() {
+                    res.push((pth.to_path_buf(), parent.to_path_buf()));
+                }
+            }
+        }
+    }
+    res
+}
+
+// move a cache entry into the quarantine tree so it will be refetched on next use
+fn quarantine(cachedir: &str, dir: &Path) -> LalResult<()> {
+    let relative = dir.strip_prefix(cachedir).unwrap_or(dir);
+    let dest = Path::new(cachedir).join("quarantine").join(relative);
+    if dest.is_dir() {
+        fs::remove_dir_all(&dest)?;
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    warn!("Quarantining {} -> {}", dir.display(), dest.display());
+    fs::rename(dir, &dest)?;
+    Ok(())
+}
+
+/// Re-hash every cached tarball against its recorded checksum
+///
+/// Tarballs that do not match are moved to `quarantine` in the cache directory,
+/// and an error is returned if any were found.
+/// Tarballs cached before checksums were recorded cannot be verified and are skipped.
+pub fn verify(cachedir: &str) -> LalResult<()> {
+    let mut corrupted = vec![];
+    let mut unverified = 0;
+    let tarballs = cached_tarballs(cachedir);
+    for &(ref tarball, ref dir) in &tarballs {
+        match Checksum::read_sidecar(tarball)? {
+            Some(sum) => {
+                if let Err(e) = sum.verify(tarball) {
+                    warn!("{}", e);
+                    corrupted.push(dir.clone());
+                } else {
+                    debug!("Verified {}", tarball.display());
+                }
+            }
+            None => {
+                debug!("No checksum recorded for {}", tarball.display());
+                unverified += 1;
+            }
+        }
+    }
+    info!("Checked {} cached tarballs ({} without a recorded checksum)",
+          tarballs.len(),
+          unverified);
+
+    for dir in &corrupted {
+        quarantine(cachedir, dir)?;
+    }
+    if !corrupted.is_empty() {
+        let qdir = Path::new(cachedir).join("quarantine");
+        return Err(CliError::CorruptedCache(corrupted.len(), qdir.display().to_string()));
+    }
+    Ok(())
+}
+
+/// A published component version in the cache
+#[derive(Serialize)]
+pub struct CachedComponent {
+    /// Name of the component
+    pub name: String,
+    /// Version number
+    pub version: Version,
+    /// Environment it was built in
+    pub environment: String,
+    /// Size in bytes
+    pub size: u64,
+    /// When it was last fetched or used (RFC 3339)
+    pub last_used: String,
+}
+
+/// A stashed build in the cache
+#[derive(Serialize)]
+pub struct StashedComponent {
+    /// Name of the component
+    pub name: String,
+    /// Name it was stashed under
+    pub code: String,
+    /// Size in bytes
+    pub size: u64,
+    /// When it was last stashed or used (RFC 3339)
+    pub last_used: String,
+}
+
+/// Everything in the cache as listed by `lal cache ls`
+#[derive(Serialize, Default)]
+pub struct CacheListing {
+    /// Published components cached from the backend
+    pub components: Vec<CachedComponent>,
+    /// Stashed builds
+    pub stash: Vec<StashedComponent>,
+}
+
+// subdirectories of a directory, sorted by name
+fn subdirs(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut res = vec![];
+    if let Ok(entries) = fs::read_dir(dir) {
+        for e in entries.filter_map(|e| e.ok()) {
+            let pth = e.path();
+            if pth.is_dir() {
+                res.push((e.file_name().to_string_lossy().into_owned(), pth));
+            }
+        }
+    }
+    res.sort_by(|a, b| a.0.cmp(&b.0));
+    res
+}
+
+// size and last use of a cache entry directory
+fn usage(dir: &Path) -> LalResult<(u64, String)> {
+    let meta = fs::metadata(dir)?;
+    Ok((dir_size(dir), mtime_of(&meta).to_rfc3339()))
+}
+
+// whether a name can be joined into a cache path without escaping its directory
+fn is_path_safe(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+// split `name[=version]` into its parts
+fn parse_coords(comp: &str) -> LalResult<(&str, Option<&str>)> {
+    let mut parts = comp.splitn(2, '=');
+    let name = parts.next().unwrap_or("");
+    if comp.to_lowercase() != comp || !is_path_safe(name) {
+        return Err(CliError::InvalidComponentName(comp.into()));
+    }
+    let version = parts.next();
+    if version.map_or(false, |v| !is_path_safe(v)) {
+        return Err(CliError::InvalidComponentVersion(comp.into()));
+    }
+    Ok((name, version))
+}
+
+/// List the published components and stashed builds in the cache
+///
+/// Only complete entries are listed; partial downloads and quarantined tarballs are not.
+pub fn listing(cachedir: &str) -> LalResult<CacheListing> {
+    let mut res = CacheListing::default();
+    let edir = Path::new(cachedir).join("environments");
+    for (env, envdir) in subdirs(&edir) {
+        for (name, namedir) in subdirs(&envdir) {
+            let mut versions = vec![];
+            for (ver, verdir) in subdirs(&namedir) {
+                if let Ok(v) = ver.parse::<Version>() {
+                    if verdir.join(format!("{}.tar.gz", name)).is_file() {
+                        versions.push((v, verdir));
+                    }
+                }
+            }
+            versions.sort_by(|a, b| b.0.cmp(&a.0)); // newest first
+            for (version, verdir) in versions {
+                let (size, last_used) = usage(&verdir)?;
+                res.components.push(CachedComponent {
+                    name: name.clone(),
+                    version: version,
+                    environment: env.clone(),
+                    size: size,
+                    last_used: last_used,
+                });
+            }
+        }
+    }
+    let sdir = Path::new(cachedir).join("stash");
+    for (name, namedir) in subdirs(&sdir) {
+        for (code, codedir) in subdirs(&namedir) {
+            if codedir.join(format!("{}.tar.gz", name)).is_file() {
+                let (size, last_used) = usage(&codedir)?;
+                res.stash.push(StashedComponent {
+                    name: name.clone(),
+                    code: code,
+                    size: size,
+                    last_used: last_used,
+                });
+            }
+        }
+    }
+    Ok(res)
+}
+
+// shorter timestamps for the human readable listing
+fn short_time(rfc3339: &str) -> String {
+    match rfc3339.parse::<DateTime<UTC>>() {
+        Ok(t) => t.format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => rfc3339.into(),
+    }
+}
+
+/// Print the contents of the cache
+pub fn ls(cachedir: &str, json_output: bool) -> LalResult<()> {
+    let res = listing(cachedir)?;
+    if json_output {
+        let encoded = serde_json::to_string_pretty(&res)?;
+        println!("{}", encoded);
+        return Ok(());
+    }
+    let mut total = 0;
+    println!("Components:");
+    for c in &res.components {
+        println!("  {:<32} {:<12} {:>8}  {}",
+                 format!("{}={}", c.name, c.version),
+                 c.environment,
+                 format_size(c.size),
+                 short_time(&c.last_used));
+        total += c.size;
+    }
+    println!("Stash:");
+    for s in &res.stash {
+        println!("  {:<32} {:<12} {:>8}  {}",
+                 format!("{}={}", s.name, s.code),
+                 "",
+                 format_size(s.size),
+                 short_time(&s.last_used));
+        total += s.size;
+    }
+    println!("Total: {}", format_size(total));
+    Ok(())
+}
+
+/// Remove components from the cache
+///
+/// The component is given as `name` for all of its cached versions and stashes,
+/// `name=version` for one published version, or `name=code` for one stashed build.
+/// Published versions can be restricted to a single environment.
+pub fn rm(cachedir: &str, comp: &str, env: Option<&str>) -> LalResult<()> {
+    let (name, version) = parse_coords(comp)?;
+    let mut dirs = vec![];
+
+    let published = version.map_or(true, |v| v.parse::<Version>().is_ok());
+    if published {
+        let edir = Path::new(cachedir).join("environments");
+        for (e, envdir) in subdirs(&edir) {
+            if env.map_or(false, |wanted| wanted != e) {
+                continue;
+            }
+            for (ver, verdir) in subdirs(&envdir.join(name)) {
+                if version.map_or(true, |v| v == ver) {
+                    dirs.push(verdir);
+                }
+            }
+        }
+    }
+    // stashes have no environment, so they only go when not filtering on one
+    if !published || (version.is_none() && env.is_none()) {
+        for (code, codedir) in subdirs(&Path::new(cachedir).join("stash").join(name)) {
+            if version.map_or(true, |v| v == code) {
+                dirs.push(codedir);
+            }
+        }
+    }
+
+    if dirs.is_empty() {
+        return Err(CliError::MissingCacheEntry(comp.into()));
+    }
+    let mut skipped = 0;
+    for d in &dirs {
+        info!("Removing {}", d.display());
+        if !remove_unused_entry(cachedir, d)? {
+            skipped += 1;
+        }
+    }
+    if skipped == dirs.len() {
+        return Err(CliError::LockedCacheEntry(comp.into()));
+    }
+    if skipped > 0 {
+        warn!("Skipped {} of {} cache entries of {} being downloaded", skipped, dirs.len(), comp);
+    }
+    Ok(())
+}
+
+/// Add a tarball to the cache as a published component
+///
+/// Useful for seeding a cache for offline use, or testing a build before publishing.
+pub fn import(cachedir: &str, tarball: &str, comp: &str, env: &str) -> LalResult<()> {
+    let (name, version) = parse_coords(comp)?;
+    let v = match version.and_then(|v| v.parse::<Version>().ok()) {
+        Some(v) => v,
+        None => return Err(CliError::InvalidComponentVersion(comp.into())),
+    };
+    info!("Importing {} as {}={} in {}", tarball, name, v, env);
+    import_tarball(cachedir, Path::new(tarball), name, &v, env)
+}
+
+/// Location of a cached tarball in one environment (or a stash)
+#[derive(Serialize)]
+pub struct CachePath {
+    /// Environment it is cached for (none for stashed builds)
+    pub environment: Option<String>,
+    /// Path to the tarball
+    pub path: String,
+}
+
+/// Print the path to the cached tarball of a component
+///
+/// The component is given as `name=version` (in every environment it is cached for,
+/// unless one is given), or as `name=code` for a stashed build.
+pub fn path(cachedir: &str, comp: &str, env: Option<&str>, json_output: bool) -> LalResult<()> {
+    let (name, version) = parse_coords(comp)?;
+    let version = match version {
+        Some(v) => v,
+        None => return Err(CliError::InvalidComponentVersion(comp.into())),
+    };
+    let mut res = vec![];
+    if let Ok(v) = version.parse::<Version>() {
+        let edir = Path::new(cachedir).join("environments");
+        for (e, _) in subdirs(&edir) {
+            if env.map_or(false, |wanted| wanted != e) {
+                continue;
+            }
+            if let Some(tarball) = cached_tarball(cachedir, name, &v, &e) {
+                res.push(CachePath {
+                    environment: Some(e),
+                    path: tarball.display().to_string(),
+                });
+            }
+        }
+    } else {
+        let tarball = Path::new(cachedir)
+            .join("stash")
+            .join(name)
+            .join(version)
+            .join(format!("{}.tar.gz", name));
+        if tarball.is_file() {
+            res.push(CachePath {
+                environment: None,
+                path: tarball.display().to_string(),
+            });
+        }
+    }
+
+    if res.is_empty() {
+        return Err(CliError::MissingCacheEntry(comp.into()));
+    }
+    if json_output {
+        let encoded = serde_json::to_string_pretty(&res)?;
+        println!("{}", encoded);
+    } else {
+        for p in &res {
+            println!("{}", p.path);
+        }
+    }
+    Ok(())
+}
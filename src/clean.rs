@@ -1,48 +1,229 @@
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, UTC, Duration, TimeZone};
 use filetime::FileTime;
 use walkdir::WalkDir;
 
-use super::LalResult;
+use super::{CliError, LalResult, CacheLock};
 
-// helper for `lal::clean`
-fn clean_in_dir(cutoff: DateTime<UTC>, dirs: WalkDir) -> LalResult<()> {
-    let drs = dirs.into_iter().filter_map(|e| e.ok()).filter(|e| e.path().is_dir());
+/// Parse a human readable size like `20G`, `1.5G`, `512M` or `1024` into bytes
+///
+/// Suffixes are binary multiples (`K`, `M`, `G`, `T`), and a trailing `B` is ignored.
+pub fn parse_size(s: &str) -> LalResult<u64> {
+    let upper = s.trim().to_uppercase();
+    let t = if upper.ends_with('B') { &upper[..upper.len() - 1] } else { &upper[..] };
+    let (num, mult) = match t.chars().last() {
+        Some('K') => (&t[..t.len() - 1], 1u64 << 10),
+        Some('M') => (&t[..t.len() - 1], 1u64 << 20),
+        Some('G') => (&t[..t.len() - 1], 1u64 << 30),
+        Some('T') => (&t[..t.len() - 1], 1u64 << 40),
+        _ => (t, 1u64),
+    };
+    match num.trim().parse::<f64>() {
+        Ok(n) if n >= 0.0 && n.is_finite() => Ok((n * mult as f64) as u64),
+        _ => Err(CliError::InvalidSize(s.into())),
+    }
+}
+
+/// Format a number of bytes in the units `parse_size` accepts
+pub fn format_size(bytes: u64) -> String {
+    let units = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, units[unit])
+}
+
+/// Last modification time of a file or directory
+///
+/// This is bumped on cache entry directories whenever they are used.
+pub fn mtime_of(meta: &fs::Metadata) -> DateTime<UTC> {
+    let mtime = FileTime::from_last_modification_time(meta);
+    UTC.ymd(1970, 1, 1).and_hms(0, 0, 0) +
+        Duration::seconds(mtime.seconds_relative_to_1970() as i64)
+}
 
-    for d in drs {
-        let pth = d.path();
-        trace!("Checking {}", pth.to_str().unwrap());
-        let mtime = FileTime::from_last_modification_time(&d.metadata().unwrap());
-        let mtimedate = UTC.ymd(1970, 1, 1).and_hms(0, 0, 0) +
-            Duration::seconds(mtime.seconds_relative_to_1970() as i64);
+/// Total size of all files below a path
+pub fn dir_size(pth: &Path) -> u64 {
+    WalkDir::new(pth)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+// a removable unit of the cache
+struct CacheEntry {
+    path: PathBuf,
+    last_used: DateTime<UTC>,
+    size: u64,
+}
+
+// component versions, stashes, cached builds and partial downloads in the cache
+fn cache_entries(cachedir: &str) -> Vec<CacheEntry> {
+    // environments/$env/$name/$version, stash/$name/$code, builds/$name/$key,
+    // partial/$env/$name/$version
+    let trees = [("environments", 3), ("stash", 2), ("builds", 2), ("partial", 3)];
+    let mut res = vec![];
+    for &(tree, depth) in &trees {
+        let dir = Path::new(cachedir).join(tree);
+        let entries = WalkDir::new(&dir).min_depth(depth).max_depth(depth);
+        for e in entries.into_iter().filter_map(|e| e.ok()).filter(|e| e.path().is_dir()) {
+            let pth = e.path();
+            trace!("Checking {}", pth.display());
+            if let Ok(meta) = e.metadata() {
+                res.push(CacheEntry {
+                    path: pth.to_path_buf(),
+                    last_used: mtime_of(&meta),
+                    size: dir_size(pth),
+                });
+            }
+        }
+    }
+    res
+}
 
-        trace!("Found {} with mtime {}", pth.to_str().unwrap(), mtimedate);
-        if mtimedate < cutoff {
-            debug!("Cleaning {}", pth.to_str().unwrap());
-            fs::remove_dir_all(pth)?;
+/// Remove a cache entry along with any parent directories it leaves empty
+///
+/// The top level trees of the cache (like `environments`) are left in place.
+pub fn remove_entry(cachedir: &str, pth: &Path) -> LalResult<()> {
+    let root = Path::new(cachedir);
+    fs::remove_dir_all(pth)?;
+    let mut parent = pth.parent();
+    while let Some(dir) = parent {
+        if !dir.starts_with(root) || dir.parent() == Some(root) || dir == root {
+            break;
         }
+        if fs::read_dir(dir)?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(dir)?;
+        parent = dir.parent();
     }
     Ok(())
 }
 
+// the lock downloads hold on an entry: in its partial directory for published components
+fn entry_lock(cachedir: &str, pth: &Path) -> Option<PathBuf> {
+    let relative = match pth.strip_prefix(cachedir) {
+        Ok(r) => r,
+        Err(_) => return None,
+    };
+    let mut parts = relative.components();
+    match parts.next().map(|c| c.as_os_str().to_string_lossy().into_owned()) {
+        Some(ref tree) if tree == "environments" => {
+            Some(Path::new(cachedir).join("partial").join(parts.as_path()).join("lock"))
+        }
+        Some(ref tree) if tree == "partial" => Some(pth.join("lock")),
+        _ => None,
+    }
+}
+
+// whether another lal process holds the lock of an entry
+fn is_downloading(cachedir: &str, pth: &Path) -> LalResult<bool> {
+    match entry_lock(cachedir, pth) {
+        Some(ref l) if l.is_file() => Ok(CacheLock::try_acquire(l)?.is_none()),
+        _ => Ok(false),
+    }
+}
+
+/// Remove a cache entry unless a download into it is in progress
+///
+/// Published components are locked the same way downloads lock them, and
+/// entries locked by another `lal` process are skipped (returning `false`).
+pub fn remove_unused_entry(cachedir: &str, pth: &Path) -> LalResult<bool> {
+    let lockpath = match entry_lock(cachedir, pth) {
+        Some(l) => l,
+        None => {
+            remove_entry(cachedir, pth)?;
+            return Ok(true);
+        }
+    };
+    let lockdir = lockpath.parent().unwrap().to_path_buf();
+    let existed = lockdir.is_dir();
+    fs::create_dir_all(&lockdir)?;
+    let _lock = match CacheLock::try_acquire(&lockpath)? {
+        Some(l) => l,
+        None => {
+            warn!("Skipping {} as it is being downloaded", pth.display());
+            return Ok(false);
+        }
+    };
+    remove_entry(cachedir, pth)?;
+    if !existed && lockdir.is_dir() {
+        remove_entry(cachedir, &lockdir)?; // only created to hold the lock
+    }
+    Ok(true)
+}
+
 /// Clean old artifacts in cache directory
 ///
-/// This does the equivalent of find CACHEDIR -mindepth 3 -maxdepth 3 -type d
-/// With the correct mtime flags, then -exec deletes these folders.
-pub fn clean(cachedir: &str, days: i64) -> LalResult<()> {
+/// Component versions, stashes, cached builds and partial downloads that have not
+/// been used in the last `days` days are removed.
+/// If a `max_size` (in bytes) is given, the least recently used entries are then
+/// removed until the cache fits within it.
+/// With `dry_run`, the entries that would be removed are printed instead.
+/// Entries that are being downloaded by another `lal` process are skipped.
+pub fn clean(cachedir: &str, days: i64, max_size: Option<u64>, dry_run: bool) -> LalResult<()> {
     let cutoff = UTC::now() - Duration::days(days);
     debug!("Cleaning all artifacts from before {}", cutoff);
 
-    // clean out environment subdirectories
-    let edir = Path::new(&cachedir).join("environments");
-    let edirs = WalkDir::new(&edir).min_depth(3).max_depth(3);
-    clean_in_dir(cutoff, edirs)?;
-
-    // clean out stash
-    let dirs = WalkDir::new(&cachedir).min_depth(3).max_depth(3);
-    clean_in_dir(cutoff, dirs)?;
+    let mut entries = cache_entries(cachedir);
+    entries.sort_by(|a, b| a.last_used.cmp(&b.last_used)); // least recently used first
+    let mut total: u64 = entries.iter().map(|e| e.size).sum();
+    if let Some(max) = max_size {
+        debug!("Cache uses {} of {}", format_size(total), format_size(max));
+    }
 
+    let mut removed = 0;
+    let mut freed: u64 = 0;
+    for e in entries {
+        let expired = e.last_used < cutoff;
+        let oversized = max_size.map_or(false, |max| total > max);
+        if !expired && !oversized {
+            break; // everything after this is more recently used
+        }
+        if !e.path.is_dir() {
+            total -= e.size; // already gone (e.g. removed by another lal clean)
+            continue;
+        }
+        // skipped entries still take up space, so only count what is removed
+        if dry_run {
+            if is_downloading(cachedir, &e.path)? {
+                warn!("Would skip {} as it is being downloaded", e.path.display());
+                continue;
+            }
+            println!("{} ({}, last used {})",
+                     e.path.display(),
+                     format_size(e.size),
+                     e.last_used.format("%Y-%m-%d %H:%M"));
+        } else {
+            debug!("Cleaning {}", e.path.display());
+            if !remove_unused_entry(cachedir, &e.path)? {
+                continue;
+            }
+        }
+        total -= e.size;
+        removed += 1;
+        freed += e.size;
+    }
+    if dry_run {
+        info!("Would remove {} cache entries freeing {}",
+              removed,
+              format_size(freed));
+    } else {
+        info!("Removed {} cache entries freeing {}",
+              removed,
+              format_size(freed));
+    }
     Ok(())
 }
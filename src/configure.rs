@@ -4,7 +4,7 @@ use std::env;
 use std::process::Command;
 use semver::Version;
 
-use super::{LalResult, Config, ConfigDefaults, CliError, config_dir};
+use super::{LalResult, Config, ConfigDefaults, ContainerRuntime, CliError, config_dir};
 
 fn executable_on_path(exe: &str) -> LalResult<()> {
     trace!("Verifying executable {}", exe);
@@ -195,9 +195,10 @@ fn create_lal_dir() -> LalResult<PathBuf> {
 /// A defaults file must be supplied to seed the new config with defined environments
 pub fn configure(save: bool, interactive: bool, defaults: &str) -> LalResult<Config> {
     let _ = create_lal_dir()?;
+    let def = ConfigDefaults::read(defaults)?;
 
     for exe in [
-        "docker",
+        def.runtime.binary(),
         "tar",
         "touch",
         "id",
@@ -209,14 +210,14 @@ pub fn configure(save: bool, interactive: bool, defaults: &str) -> LalResult<Con
     {
         executable_on_path(exe)?;
     }
-    docker_sanity()?;
-    docker_version_check()?;
+    if def.runtime == ContainerRuntime::Docker {
+        docker_sanity()?;
+        docker_version_check()?;
+    }
     kernel_sanity()?;
     ssl_cert_sanity()?;
     non_root_sanity()?;
 
-    let def = ConfigDefaults::read(defaults)?;
-
     // Enforce minimum_lal version check here if it's set in the defaults file
     if let Some(minlal) = def.minimum_lal.clone() {
         lal_version_check(&minlal)?;
@@ -36,6 +36,40 @@ pub struct Mount {
     pub readonly: bool,
 }
 
+/// Container runtime used to run builds and shells
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ContainerRuntime {
+    /// Docker (the default)
+    #[serde(rename = "docker")]
+    Docker,
+    /// Podman, usually running rootless
+    ///
+    /// Needs Podman 4.3 or later for `--userns=keep-id:uid=1000,gid=1000`.
+    #[serde(rename = "podman")]
+    Podman,
+}
+
+/// Docker is the default runtime
+impl Default for ContainerRuntime {
+    fn default() -> Self { ContainerRuntime::Docker }
+}
+
+impl ContainerRuntime {
+    /// Name of the executable for the runtime
+    pub fn binary(&self) -> &'static str {
+        match *self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+
+    /// Whether the runtime maps the host user into containers with `--userns=keep-id`
+    ///
+    /// Runtimes that cannot do this need a derived image with the ids of the `lal` user
+    /// changed when the host user is not 1000:1000.
+    pub fn keeps_user_id(&self) -> bool { *self == ContainerRuntime::Podman }
+}
+
 /// Representation of `~/.lal/config`
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Clone)]
@@ -56,8 +90,19 @@ pub struct Config {
     pub interactive: bool,
     /// Minimum version restriction of lal enforced by this config
     pub minimum_lal: Option<String>,
+    /// Number of parallel downloads used by `lal fetch` and `lal update`
+    #[serde(default = "default_jobs")]
+    pub jobs: usize,
+    /// Size limit for the cache enforced by `lal clean` (e.g. `20G`)
+    #[serde(default)]
+    pub cache_max_size: Option<String>,
+    /// Container runtime to use for builds and shells
+    #[serde(default)]
+    pub runtime: ContainerRuntime,
 }
 
+fn default_jobs() -> usize { 4 }
+
 /// Representation of a configuration defaults file
 ///
 /// This file is being used to generate the config when using `lal configure`
@@ -71,6 +116,9 @@ pub struct ConfigDefaults {
     pub mounts: Vec<Mount>,
     /// Optional minimum version restriction of lal
     pub minimum_lal: Option<String>,
+    /// Container runtime to use for builds and shells
+    #[serde(default)]
+    pub runtime: ContainerRuntime,
 }
 
 impl ConfigDefaults {
@@ -88,7 +136,7 @@ impl ConfigDefaults {
     }
 }
 
-fn check_mount(name: &str) -> LalResult<String> {
+fn check_mount(name: &str, runtime: ContainerRuntime) -> LalResult<String> {
     // See if it's a path first:
     let home = find_home_dir();
     let src = name.to_string().replace("~", &home.to_string_lossy());
@@ -101,7 +149,9 @@ fn check_mount(name: &str) -> LalResult<String> {
     // Otherwise, if it does not contain a slash
     if !name.contains("/") {
         use std::process::Command;
-        let volume_output = Command::new("docker").args(vec!["volume", "ls", "-q"]).output()?;
+        let volume_output = Command::new(runtime.binary())
+            .args(vec!["volume", "ls", "-q"])
+            .output()?;
         let volstr = String::from_utf8_lossy(&volume_output.stdout);
         // If it exists, do nothing:
         if volstr.contains(name) {
@@ -109,7 +159,7 @@ fn check_mount(name: &str) -> LalResult<String> {
             return Ok(name.into());
         }
         // Otherwise warn
-        warn!("Discarding missing docker volume {}", name);
+        warn!("Discarding missing {} volume {}", runtime.binary(), name);
         Err(CliError::MissingMount(name.into()))
     } else {
         warn!("Discarding missing mount {}", src);
@@ -134,7 +184,7 @@ impl Config {
         let mut mounts = vec![];
         for mount in defaults.mounts {
             // Check src for pathiness or prepare a docker volume
-            match check_mount(&mount.src) {
+            match check_mount(&mount.src, defaults.runtime) {
                 Ok(src) => {
                     let mut mountnew = mount.clone();
                     mountnew.src = src; // update potentially mapped source
@@ -153,6 +203,9 @@ impl Config {
             backend: defaults.backend,
             minimum_lal: defaults.minimum_lal,
             interactive: true,
+            jobs: default_jobs(),
+            cache_max_size: None,
+            runtime: defaults.runtime,
         }
     }
 
@@ -199,9 +252,11 @@ impl Config {
     }
 
     /// Resolve an arbitrary container shorthand
+    ///
+    /// Entries pinned with a name like `image@sha256:..` have the digest split out.
     pub fn get_container(&self, env: String) -> LalResult<Container> {
         if let Some(container) = self.environments.get(&env) {
-            return Ok(container.clone());
+            return Ok(container.clone().pinned());
         }
         Err(CliError::MissingEnvironment(env))
     }
@@ -3,6 +3,8 @@ use std::io;
 use hyper;
 use serde_json;
 
+use super::Version;
+
 /// The one and only error type for the lal library
 ///
 /// Every command will raise one of these on failure, and these is some reuse between
@@ -62,6 +64,14 @@ pub enum CliError {
     NoSupportedEnvironments,
     /// Environment in manifest is not in the supported environments
     UnsupportedEnvironment,
+    /// Component not found anywhere in the dependency tree
+    NotInDependencyTree(String),
+    /// Unknown output format for the dependency graph
+    InvalidGraphFormat(String),
+    /// Another propagation is unfinished in the workspace
+    PropagationInProgress(String, String),
+    /// The published versions of a repo to propagate to could not be listed
+    UnknownNextVersion(String, String),
 
     // env related errors
     /// Specified environment is not present in the main config
@@ -76,20 +86,38 @@ pub enum CliError {
     BuildScriptNotExecutable(String),
     /// BUILD script not found
     MissingBuildScript,
+    /// Some of the builds of several components or configurations failed
+    BuildFailures(usize, usize),
 
     // script errors
     /// Script not found in local .lal/scripts/ directory
     MissingScript(String),
 
     // cache errors
-    /// Failed to find a tarball after fetching from artifactory
+    /// Failed to find a tarball after fetching it from the backend
     MissingTarball,
     /// Failed to find build artifacts in OUTPUT after a build or before stashing
     MissingBuild,
+    /// Tarball did not match its recorded or advertised checksum
+    ChecksumMismatch(String, String, String),
+    /// Corrupted tarballs found (and quarantined) in the cache
+    CorruptedCache(usize, String),
+    /// Unparseable size given for a cache limit
+    InvalidSize(String),
+    /// Nothing matching in the cache
+    MissingCacheEntry(String),
+    /// Every matching cache entry is locked by a download
+    LockedCacheEntry(String),
+    /// Component given without a usable `=version` suffix
+    InvalidComponentVersion(String),
+    /// Version that is neither an integer nor a semver version
+    InvalidVersionString(String),
+    /// Version constraint that could not be parsed
+    InvalidVersionRequirement(String),
 
     // stash errors
-    /// Invalid integer name used with lal stash
-    InvalidStashName(u32),
+    /// Name that parses as a version used with lal stash
+    InvalidStashName(String),
     /// Failed to find stashed artifact in the lal cache
     MissingStashArtifact(String),
 
@@ -107,6 +135,14 @@ pub enum CliError {
     BackendFailure(String),
     /// No version found at same version across `supportedEnvironments`
     NoIntersectedVersion(String),
+    /// No version satisfying a constraint found across `supportedEnvironments`
+    NoMatchingVersion(String, String),
+    /// Dependencies differ from what is recorded in `.lal/lock.json`
+    LockDrift(String),
+    /// Component (at an optional version) not found in the cache in offline mode
+    OfflineUnavailable(String, Option<Version>, String),
+    /// Operation requires network access but offline mode is enabled
+    OfflineOperation(String),
 
     // publish errors
     /// Missing release build
@@ -197,6 +233,18 @@ impl fmt::Display for CliError {
             CliError::UnsupportedEnvironment => {
                 write!(f, "manifest.environment must exist in manifest.supportedEnvironments")
             }
+            CliError::NotInDependencyTree(ref s) => {
+                write!(f, "{} is not in the dependency tree", s)
+            }
+            CliError::InvalidGraphFormat(ref s) => {
+                write!(f, "Unknown graph format '{}' (use dot, mermaid or json)", s)
+            }
+            CliError::PropagationInProgress(ref c, ref env) => {
+                write!(f, "Propagation of {} in {} unfinished in the workspace", c, env)
+            }
+            CliError::UnknownNextVersion(ref c, ref env) => {
+                write!(f, "Unable to list the published versions of {} in {} to version it", c, env)
+            }
             CliError::MissingEnvironment(ref s) => {
                 write!(f, "Environment '{}' not found in ~/.lal/config", s)
             }
@@ -210,14 +258,42 @@ impl fmt::Display for CliError {
                 write!(f, "BUILD script at {} is not executable", s)
             }
             CliError::MissingBuildScript => write!(f, "No `BUILD` script found"),
+            CliError::BuildFailures(n, total) => write!(f, "{} of {} builds failed", n, total),
             CliError::MissingScript(ref s) => {
                 write!(f, "Missing script '{}' in local folder .lal/scripts/", s)
             }
-            CliError::MissingTarball => write!(f, "Tarball missing in PWD"),
+            CliError::MissingTarball => write!(f, "Tarball missing after download"),
             CliError::MissingBuild => write!(f, "No build found in OUTPUT"),
-            CliError::InvalidStashName(n) => {
+            CliError::ChecksumMismatch(ref s, ref expected, ref actual) => {
                 write!(f,
-                       "Invalid name '{}' to stash under - must not be an integer",
+                       "Checksum mismatch for {} - expected {} got {}",
+                       s,
+                       expected,
+                       actual)
+            }
+            CliError::CorruptedCache(n, ref s) => {
+                write!(f, "{} corrupted tarball(s) found in the cache - moved to {}", n, s)
+            }
+            CliError::MissingCacheEntry(ref s) => write!(f, "{} not found in the cache", s),
+            CliError::LockedCacheEntry(ref s) => {
+                write!(f, "{} is being downloaded by another lal process - nothing removed", s)
+            }
+            CliError::InvalidComponentVersion(ref s) => {
+                write!(f, "Invalid component {} - expected <name>=<version>", s)
+            }
+            CliError::InvalidVersionString(ref s) => {
+                write!(f, "Invalid version '{}' - expected an integer or a semver version", s)
+            }
+            CliError::InvalidVersionRequirement(ref s) => {
+                write!(f, "Invalid version constraint '{}'", s)
+            }
+            CliError::InvalidSize(ref s) => {
+                write!(f, "Invalid size '{}' - expected a number with an optional K/M/G/T suffix",
+                       s)
+            }
+            CliError::InvalidStashName(ref n) => {
+                write!(f,
+                       "Invalid name '{}' to stash under - must not be a version",
                        n)
             }
             CliError::MissingStashArtifact(ref s) => {
@@ -237,6 +313,25 @@ impl fmt::Display for CliError {
             CliError::NoIntersectedVersion(ref s) => {
                 write!(f, "No version of {} found across all environments", s)
             }
+            CliError::NoMatchingVersion(ref s, ref req) => {
+                write!(f, "No version of {} matching {} found across all environments", s, req)
+            }
+            CliError::LockDrift(ref s) => {
+                write!(f, "Dependencies differ from .lal/lock.json - {}", s)
+            }
+            CliError::OfflineUnavailable(ref name, Some(ref v), ref env) => {
+                write!(f,
+                       "{} version {} for {} is not in the cache (running offline)",
+                       name,
+                       v,
+                       env)
+            }
+            CliError::OfflineUnavailable(ref name, None, ref env) => {
+                write!(f, "No versions of {} for {} in the cache (running offline)", name, env)
+            }
+            CliError::OfflineOperation(ref s) => {
+                write!(f, "`lal {}` needs network access - not possible with --offline", s)
+            }
             CliError::MissingReleaseBuild => write!(f, "Missing release build"),
             CliError::MissingBackendCredentials => {
                 write!(f, "Missing backend credentials in ~/.lal/config")
@@ -3,12 +3,12 @@
 use std::io::prelude::*;
 use std::fs::File;
 use std::path::Path;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use serde_json;
 
 use walkdir::WalkDir;
 
-use super::{Manifest, Lockfile, CliError, LalResult};
+use super::{Manifest, Lockfile, CliError, LalResult, Version};
 
 #[derive(Deserialize)]
 struct PartialLock {
@@ -150,19 +150,19 @@ pub fn verify_global_versions(lf: &Lockfile, m: &Manifest) -> LalResult<()> {
     let all_deps = m.all_dependencies();
     for (name, dep) in &lf.dependencies {
         let v = dep.version
-            .parse::<u32>()
+            .parse::<Version>()
             .map_err(|e| {
-                debug!("Failed to parse first version of {} as int ({:?})", name, e);
+                debug!("Failed to parse first version of {} as a version ({})", name, e);
                 CliError::NonGlobalDependencies(name.clone())
             })?;
         // also ensure it matches the version in the manifest
-        let vreq = *all_deps
+        let vreq = all_deps
             .get(name)
             .ok_or_else(|| {
                 // This is a first level dependency - it should be in the manifest
                 CliError::ExtraneousDependencies(name.clone())
             })?;
-        if v != vreq {
+        if !vreq.matches(&v) {
             warn!("Dependency {} has version {}, but manifest requires {}",
                   name,
                   v,
@@ -195,7 +195,17 @@ pub fn verify_consistent_dependency_versions(lf: &Lockfile, m: &Manifest) -> Lal
 }
 
 /// Strict requirement for verifier - all deps must be built in same environment
+///
+/// Native builds only claim their environment as they ran outside its container,
+/// so these are flagged as well.
 pub fn verify_environment_consistency(lf: &Lockfile, env: &str) -> LalResult<()> {
+    for (name, natives) in lf.find_all_native_builds() {
+        if natives.contains("true") {
+            warn!("{} was built natively on a host rather than in the {} container",
+                  name,
+                  env);
+        }
+    }
     for (name, envs) in lf.find_all_environments() {
         debug!("Found environment(s) for {} as {:?}", name, envs);
         if envs.len() != 1 {
@@ -210,3 +220,26 @@ pub fn verify_environment_consistency(lf: &Lockfile, env: &str) -> LalResult<()>
     }
     Ok(())
 }
+
+/// Digest -> names of components built with it in an environment
+pub type DigestUsage = BTreeMap<String, BTreeSet<String>>;
+
+fn collect_container_digests(lf: &Lockfile, env: &str, acc: &mut DigestUsage) {
+    for (name, dep) in &lf.dependencies {
+        if let Some(ref digest) = dep.container.digest {
+            if dep.environment == env {
+                acc.entry(digest.clone()).or_insert_with(BTreeSet::new).insert(name.clone());
+            }
+        }
+        collect_container_digests(dep, env, acc);
+    }
+}
+
+/// Components built with each image digest of an environment
+///
+/// Dependencies built before digests were recorded are not included.
+pub fn find_container_digests(lf: &Lockfile, env: &str) -> DigestUsage {
+    let mut acc = BTreeMap::new();
+    collect_container_digests(lf, env, &mut acc);
+    acc
+}
@@ -19,6 +19,9 @@ pub struct Container {
     pub name: String,
     /// The tag to use
     pub tag: String,
+    /// Content digest of the image (e.g. `sha256:..`) pinning the exact image used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
 }
 
 impl Container {
@@ -27,12 +30,43 @@ impl Container {
         Container {
             name: name.into(),
             tag: "latest".into(),
+            digest: None,
+        }
+    }
+
+    /// Container with a digest given as part of the name moved into `digest`
+    ///
+    /// This allows config entries to pin an image with a name like `image@sha256:..`.
+    pub fn pinned(self) -> Self {
+        if self.digest.is_some() || !self.name.contains('@') {
+            return self;
+        }
+        let split: Vec<String> = self.name.splitn(2, '@').map(String::from).collect();
+        Container {
+            name: split[0].clone(),
+            tag: self.tag,
+            digest: Some(split[1].clone()),
+        }
+    }
+
+    /// The same image pinned to a specific digest
+    pub fn with_digest(self, digest: Option<String>) -> Self {
+        Container {
+            name: self.name,
+            tag: self.tag,
+            digest: digest,
         }
     }
 }
 
+/// Image reference usable with docker, using the digest when pinned
 impl fmt::Display for Container {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}:{}", self.name, self.tag) }
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.digest {
+            Some(ref d) => write!(f, "{}@{}", self.name, d),
+            None => write!(f, "{}:{}", self.name, self.tag),
+        }
+    }
 }
 
 /// Convenience default for functions that require Lockfile inspection
@@ -42,6 +76,7 @@ impl Default for Container {
         Container {
             name: "ubuntu".into(),
             tag: "xenial".into(),
+            digest: None,
         }
     }
 }
@@ -51,17 +86,24 @@ impl Container {
     ///
     /// This will split the container on `:` to actually fetch the tag, and if no tag
     /// was present, it will assume tag is latest as per docker conventions.
+    /// A trailing `@sha256:..` digest pins the image.
     pub fn new(container: &str) -> Container {
-        let split: Vec<&str> = container.split(':').collect();
+        let pin: Vec<&str> = container.splitn(2, '@').collect();
+        let image = pin[0];
+        let split: Vec<&str> = image.split(':').collect();
         let tag = if split.len() == 2 { split[1] } else { "latest" };
-        let cname = if split.len() == 2 { split[0] } else { container };
+        let cname = if split.len() == 2 { split[0] } else { image };
         Container {
             name: cname.into(),
             tag: tag.into(),
+            digest: pin.get(1).map(|d| d.to_string()),
         }
     }
 }
 
+// only record `native` for native builds
+fn is_false(b: &bool) -> bool { !*b }
+
 /// Representation of `lockfile.json`
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug)]
@@ -84,6 +126,9 @@ pub struct Lockfile {
     pub tool: String,
     /// Built timestamp
     pub built: Option<String>,
+    /// Whether the build ran directly on the host rather than in the container
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub native: bool,
     /// Recursive map of dependencies used
     pub dependencies: BTreeMap<String, Lockfile>,
 }
@@ -117,6 +162,7 @@ impl Lockfile {
             environment: env.into(),
             dependencies: BTreeMap::new(),
             sha: None,
+            native: false,
         }
     }
 
@@ -170,6 +216,12 @@ impl Lockfile {
         self
     }
 
+    /// Record whether the build runs natively on the host
+    pub fn set_native(mut self, native: bool) -> Self {
+        self.native = native;
+        self
+    }
+
     /// Attach a name to the lockfile
     pub fn set_name(mut self, name: &str) -> Self {
         self.name = name.into();
@@ -198,6 +250,8 @@ impl Lockfile {
             self.version.clone()
         } else if key == "environment" {
             self.environment.clone()
+        } else if key == "native" {
+            self.native.to_string()
         } else {
             unreachable!("Only using get_value internally");
         }
@@ -246,6 +300,9 @@ impl Lockfile {
     /// List all used environments used of each dependency
     pub fn find_all_environments(&self) -> ValueUsage { self.find_all_values("environment") }
 
+    /// List whether each dependency was built natively (`"true"` or `"false"`)
+    pub fn find_all_native_builds(&self) -> ValueUsage { self.find_all_values("native") }
+
     /// List all dependency names used by each dependency (not transitively)
     pub fn find_all_dependency_names(&self) -> ValueUsage {
         let mut acc = HashMap::new();
@@ -5,7 +5,7 @@ use std::vec::Vec;
 use serde_json;
 use std::path::{Path, PathBuf};
 
-use super::{CliError, LalResult};
+use super::{CliError, LalResult, VersionReq};
 
 /// A startup helper used in a few places
 pub fn create_lal_subdir(pwd: &PathBuf) -> LalResult<()> {
@@ -48,9 +48,9 @@ pub struct Manifest {
     /// Components and their available configurations that are buildable
     pub components: BTreeMap<String, ComponentConfiguration>,
     /// Dependencies that are always needed
-    pub dependencies: BTreeMap<String, u32>,
+    pub dependencies: BTreeMap<String, VersionReq>,
     /// Development dependencies
-    pub devDependencies: BTreeMap<String, u32>,
+    pub devDependencies: BTreeMap<String, VersionReq>,
 
     /// Internal path of this manifest
     #[serde(skip_serializing, skip_deserializing)]
@@ -116,10 +116,10 @@ impl Manifest {
         }
     }
     /// Merge dependencies and devDependencies into one convenience map
-    pub fn all_dependencies(&self) -> BTreeMap<String, u32> {
+    pub fn all_dependencies(&self) -> BTreeMap<String, VersionReq> {
         let mut deps = self.dependencies.clone();
         for (k, v) in &self.devDependencies {
-            deps.insert(k.clone(), *v);
+            deps.insert(k.clone(), v.clone());
         }
         deps
     }
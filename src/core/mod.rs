@@ -1,15 +1,19 @@
 pub use self::errors::{CliError, LalResult};
 pub use self::manifest::{Manifest, ComponentConfiguration, ManifestLocation};
 pub use self::lockfile::{Lockfile, Container};
-pub use self::config::{Config, ConfigDefaults, Mount, config_dir};
+pub use self::config::{Config, ConfigDefaults, ContainerRuntime, Mount, config_dir};
 pub use self::sticky::StickyOptions;
 pub use self::ensure::ensure_dir_exists_fresh;
+pub use self::version::{Version, VersionReq};
+pub use self::resolution::{Resolution, ResolvedDependency};
 
 mod config;
 mod errors;
 mod lockfile;
 mod sticky;
 mod ensure;
+mod version;
+mod resolution;
 
 /// Manifest module can be used directly
 pub mod manifest;
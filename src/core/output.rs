@@ -4,17 +4,21 @@ use std::path::Path;
 use super::{CliError, LalResult};
 
 /// Helper for stash and build
-pub fn tar(tarball: &Path) -> LalResult<()> {
-    info!("Taring OUTPUT");
+pub fn tar(tarball: &Path) -> LalResult<()> { tar_dir(tarball, Path::new("OUTPUT")) }
+
+/// Tar up the contents of a directory in the same way as `OUTPUT`
+pub fn tar_dir(tarball: &Path, dir: &Path) -> LalResult<()> {
+    info!("Taring {}", dir.display());
+    let prefix = format!("{}/", dir.display());
     let mut args: Vec<String> = vec![
         "czf".into(),
         tarball.to_str().unwrap().into(), // path created internally - always valid unicode
-        "--transform=s,^OUTPUT/,,".into(), // remove leading OUTPUT
+        format!("--transform=s,^{},,", prefix), // remove the leading directory
     ];
 
     // Avoid depending on wildcards (which would also hide hidden files)
     // All links, hidden files, and regular files should go into the tarball.
-    let findargs = vec!["OUTPUT/", "-type", "f", "-o", "-type", "l"];
+    let findargs = vec![&prefix[..], "-type", "f", "-o", "-type", "l"];
     debug!("find {}", findargs.join(" "));
     let find_output = Command::new("find").args(&findargs).output()?;
     let find_str = String::from_utf8_lossy(&find_output.stdout);
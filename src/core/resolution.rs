@@ -0,0 +1,94 @@
+use std::fs;
+use std::env;
+use std::io::prelude::{Read, Write};
+use std::path::Path;
+use std::collections::BTreeMap;
+use serde_json;
+
+use super::{LalResult, Lockfile, Version, VersionReq};
+use manifest::create_lal_subdir;
+
+/// A published component as recorded in `.lal/lock.json`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ResolvedDependency {
+    /// The exact version
+    pub version: Version,
+    /// Environment it was built in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    /// Sha256 of its tarball (only recorded for what is installed into INPUT)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Its own dependencies (as recorded in its lockfile)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, ResolvedDependency>,
+}
+
+impl ResolvedDependency {
+    /// Entry for a published component from its lockfile (without a checksum)
+    pub fn from_lockfile(lf: &Lockfile) -> LalResult<ResolvedDependency> {
+        let mut deps = BTreeMap::new();
+        for (name, dep) in &lf.dependencies {
+            deps.insert(name.clone(), ResolvedDependency::from_lockfile(dep)?);
+        }
+        Ok(ResolvedDependency {
+            version: lf.version.parse()?,
+            environment: Some(lf.environment.clone()),
+            sha256: None,
+            dependencies: deps,
+        })
+    }
+}
+
+/// Representation of `.lal/lock.json`
+///
+/// This records the exact version, environment and tarball checksum of every dependency
+/// installed by `lal fetch`, along with their dependency trees.
+/// Versions that constraints in the manifest were resolved to are kept until the
+/// next `lal update --save`, and `lal fetch --locked` installs exactly what is recorded.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Resolution {
+    /// Dependencies and devDependencies
+    pub dependencies: BTreeMap<String, ResolvedDependency>,
+}
+
+impl Resolution {
+    /// Read and deserialize a Resolution from `.lal/lock.json`
+    pub fn read() -> LalResult<Resolution> {
+        let lock_path = Path::new(".lal/lock.json");
+        if !lock_path.exists() {
+            return Ok(Resolution::default()); // nothing resolved yet
+        }
+        let mut lock_data = String::new();
+        fs::File::open(&lock_path)?.read_to_string(&mut lock_data)?;
+        let res = serde_json::from_str(&lock_data)?;
+        Ok(res)
+    }
+
+    /// Overwrite `.lal/lock.json` with the current contents
+    pub fn write(&self) -> LalResult<()> {
+        let pwd = env::current_dir()?;
+        create_lal_subdir(&pwd)?; // create the `.lal` subdir if it's not there already
+        let lock_path = Path::new(".lal/lock.json");
+        let encoded = serde_json::to_string_pretty(self)?;
+
+        let mut f = fs::File::create(&lock_path)?;
+        write!(f, "{}\n", encoded)?;
+        debug!("Wrote {}: \n{}", lock_path.display(), encoded);
+        Ok(())
+    }
+
+    /// The version to install for a dependency with a given requirement
+    ///
+    /// Exact requirements need no resolution. Constraints use the recorded version,
+    /// unless there is none, or it no longer satisfies the constraint.
+    pub fn version_for(&self, name: &str, req: &VersionReq) -> Option<Version> {
+        if let Some(v) = req.exact() {
+            return Some(v.clone());
+        }
+        match self.dependencies.get(name) {
+            Some(d) if req.matches(&d.version) => Some(d.version.clone()),
+            _ => None,
+        }
+    }
+}
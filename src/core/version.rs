@@ -0,0 +1,271 @@
+use std::fmt;
+use std::str::FromStr;
+
+use semver;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, Visitor, Unexpected};
+
+use super::CliError;
+
+/// Version of a published component
+///
+/// Components are normally versioned with a monotonically increasing integer,
+/// but externally versioned components can use semver style versions (`1.4.2`, `2.0.0-rc1`).
+///
+/// Integers are ordered numerically, and semver versions by semver precedence.
+/// A component should stick to one scheme, but if both are found for the same component,
+/// all integer versions order before all semver versions.
+///
+/// Integer versions serialize as plain numbers so that existing manifests are unchanged.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Version {
+    /// A build number
+    Integer(u32),
+    /// A semantic version
+    Semver(semver::Version),
+}
+
+impl From<u32> for Version {
+    fn from(n: u32) -> Self { Version::Integer(n) }
+}
+
+impl FromStr for Version {
+    type Err = CliError;
+
+    /// Parse an integer or a full `major.minor.patch[-pre]` semver version
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(n) = s.parse::<u32>() {
+            return Ok(Version::Integer(n));
+        }
+        semver::Version::parse(s)
+            .map(Version::Semver)
+            .map_err(|_| CliError::InvalidVersionString(s.into()))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Version::Integer(n) => write!(f, "{}", n),
+            Version::Semver(ref v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            Version::Integer(n) => serializer.serialize_u32(n),
+            Version::Semver(ref v) => serializer.serialize_str(&v.to_string()),
+        }
+    }
+}
+
+struct VersionVisitor;
+
+impl<'de> Visitor<'de> for VersionVisitor {
+    type Value = Version;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an integer or a semver version string")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Version, E> {
+        if v > u64::from(u32::max_value()) {
+            return Err(E::invalid_value(Unexpected::Unsigned(v), &self));
+        }
+        Ok(Version::Integer(v as u32))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Version, E> {
+        if v < 0 {
+            return Err(E::invalid_value(Unexpected::Signed(v), &self));
+        }
+        self.visit_u64(v as u64)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Version, E> {
+        v.parse().map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(VersionVisitor)
+    }
+}
+
+/// Comparison operator in a constraint on integer versions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// `=`
+    Eq,
+    /// `>`
+    Gt,
+    /// `>=`
+    GtEq,
+    /// `<`
+    Lt,
+    /// `<=`
+    LtEq,
+}
+
+// parse a comma separated list of integer comparators like `>=40, <50`
+fn parse_integer_req(s: &str) -> Option<Vec<(Op, u32)>> {
+    s.split(',')
+        .map(|part| {
+            let p = part.trim();
+            let (op, n) = if p.starts_with(">=") {
+                (Op::GtEq, &p[2..])
+            } else if p.starts_with("<=") {
+                (Op::LtEq, &p[2..])
+            } else if p.starts_with('>') {
+                (Op::Gt, &p[1..])
+            } else if p.starts_with('<') {
+                (Op::Lt, &p[1..])
+            } else if p.starts_with('=') {
+                (Op::Eq, &p[1..])
+            } else if p.starts_with('~') || p.starts_with('^') {
+                // build numbers carry no compatibility information
+                (Op::GtEq, &p[1..])
+            } else {
+                (Op::Eq, p)
+            };
+            n.trim().parse::<u32>().ok().map(|n| (op, n))
+        })
+        .collect()
+}
+
+/// Version requirement of a dependency in the manifest
+///
+/// This is either an exact version, or a constraint that is resolved to the highest
+/// matching version when updating (recorded in `.lal/lock.json`).
+///
+/// Constraints on integer versions are comma separated comparisons (`>=40, <50`),
+/// where `~42` and `^42` mean any version from 42 onwards.
+/// Constraints on semver versions follow semver rules (`~1.2`, `>=1.0, <2.0`).
+#[derive(Clone, Debug, PartialEq)]
+pub enum VersionReq {
+    /// An exact version
+    Exact(Version),
+    /// A constraint on integer versions (as written)
+    Integer(String, Vec<(Op, u32)>),
+    /// A constraint on semver versions (as written)
+    Semver(String, semver::VersionReq),
+}
+
+impl VersionReq {
+    /// The version required if this is an exact version
+    pub fn exact(&self) -> Option<&Version> {
+        match *self {
+            VersionReq::Exact(ref v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Whether a version satisfies this requirement
+    pub fn matches(&self, version: &Version) -> bool {
+        match (self, version) {
+            (&VersionReq::Exact(ref v), _) => v == version,
+            (&VersionReq::Integer(_, ref cmps), &Version::Integer(n)) => {
+                cmps.iter().all(|&(ref op, m)| match *op {
+                    Op::Eq => n == m,
+                    Op::Gt => n > m,
+                    Op::GtEq => n >= m,
+                    Op::Lt => n < m,
+                    Op::LtEq => n <= m,
+                })
+            }
+            (&VersionReq::Semver(_, ref req), &Version::Semver(ref v)) => req.matches(v),
+            _ => false,
+        }
+    }
+
+    /// The highest of a set of versions satisfying this requirement
+    pub fn best_match<'a, I>(&self, versions: I) -> Option<Version>
+    where
+        I: IntoIterator<Item = &'a Version>,
+    {
+        versions.into_iter().filter(|v| self.matches(v)).max().cloned()
+    }
+}
+
+impl From<Version> for VersionReq {
+    fn from(v: Version) -> Self { VersionReq::Exact(v) }
+}
+
+impl FromStr for VersionReq {
+    type Err = CliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(v) = s.parse::<Version>() {
+            return Ok(VersionReq::Exact(v));
+        }
+        if let Some(cmps) = parse_integer_req(s) {
+            return Ok(VersionReq::Integer(s.into(), cmps));
+        }
+        semver::VersionReq::parse(s)
+            .map(|req| VersionReq::Semver(s.into(), req))
+            .map_err(|_| CliError::InvalidVersionRequirement(s.into()))
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VersionReq::Exact(ref v) => write!(f, "{}", v),
+            VersionReq::Integer(ref s, _) |
+            VersionReq::Semver(ref s, _) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Serialize for VersionReq {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match *self {
+            VersionReq::Exact(ref v) => v.serialize(serializer),
+            VersionReq::Integer(ref s, _) |
+            VersionReq::Semver(ref s, _) => serializer.serialize_str(s),
+        }
+    }
+}
+
+struct VersionReqVisitor;
+
+impl<'de> Visitor<'de> for VersionReqVisitor {
+    type Value = VersionReq;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an integer, a semver version or a version constraint")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<VersionReq, E> {
+        VersionVisitor.visit_u64(v).map(VersionReq::Exact)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<VersionReq, E> {
+        VersionVisitor.visit_i64(v).map(VersionReq::Exact)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<VersionReq, E> {
+        v.parse().map_err(|_| E::invalid_value(Unexpected::Str(v), &self))
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionReq {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(VersionReqVisitor)
+    }
+}
@@ -3,13 +3,13 @@ use std::vec::Vec;
 
 use super::{StickyOptions, LalResult, CliError, Container, Config};
 
-/// Pull the current environment from docker
-pub fn update(container: &Container, env: &str) -> LalResult<()> {
+/// Pull the current environment with the configured container runtime
+pub fn update(cfg: &Config, container: &Container, env: &str) -> LalResult<()> {
     info!("Updating {} container", env);
     let args: Vec<String> = vec!["pull".into(), format!("{}", container)];
-    trace!("Docker pull {}", container);
-    let s = Command::new("docker").args(&args).status()?;
-    trace!("Exited docker");
+    trace!("{} pull {}", cfg.runtime.binary(), container);
+    let s = Command::new(cfg.runtime.binary()).args(&args).status()?;
+    trace!("Exited {}", cfg.runtime.binary());
     if !s.success() {
         return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
     }
@@ -2,7 +2,7 @@ use std::fs;
 use std::path::Path;
 
 use storage::CachedBackend;
-use super::{LalResult, CliError};
+use super::{LalResult, CliError, Version};
 
 /// Export a specific component from the storage backend
 pub fn export<T: CachedBackend + ?Sized>(
@@ -29,8 +29,8 @@ pub fn export<T: CachedBackend + ?Sized>(
     let mut component_name = comp; // this is only correct if no =version suffix
     let tarname = if comp.contains('=') {
         let pair: Vec<&str> = comp.split('=').collect();
-        if let Ok(n) = pair[1].parse::<u32>() {
-            // standard fetch with an integer version
+        if let Ok(n) = pair[1].parse::<Version>() {
+            // standard fetch with a published version
             component_name = pair[0]; // save so we have sensible tarball names
             backend.retrieve_published_component(pair[0], Some(n), env)?.0
         } else {
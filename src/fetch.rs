@@ -1,8 +1,12 @@
 use std::fs;
 use std::path::Path;
+use std::collections::BTreeMap;
 
-use storage::CachedBackend;
-use super::{CliError, LalResult, Lockfile, Manifest};
+use storage::{Backend, CachedBackend, Checksum, HashType, cached_tarball};
+use super::{CliError, LalResult, Lockfile, Manifest, Version, VersionReq, Resolution,
+            ResolvedDependency};
+use update::resolve_version;
+use resolve::check_published;
 
 fn clean_input() {
     let input = Path::new("./INPUT");
@@ -11,15 +15,110 @@ fn clean_input() {
     }
 }
 
+/// Entry for `.lal/lock.json` of a published component installed in `./INPUT`
+///
+/// The version, environment and tree come from its lockfile, and the checksum from
+/// the cached tarball it was unpacked from (if it is still cached).
+pub fn installed_resolution(cache: &str, name: &str, env: &str) -> LalResult<ResolvedDependency> {
+    let lock_path = Path::new("./INPUT").join(name).join("lockfile.json");
+    let lf = Lockfile::from_path(&lock_path, name)?;
+    let mut dep = ResolvedDependency::from_lockfile(&lf)?;
+    if let Some(tarball) = cached_tarball(cache, name, &dep.version, env) {
+        dep.sha256 = Some(Checksum::of_file(HashType::Sha256, &tarball)?.hex);
+    }
+    Ok(dep)
+}
+
+// versions to install with `--locked` - failing if the manifest no longer agrees
+fn locked_versions(
+    manifest: &Manifest,
+    resolution: &Resolution,
+    reqs: &BTreeMap<String, VersionReq>,
+    env: &str,
+) -> LalResult<BTreeMap<String, Version>> {
+    let all_deps = manifest.all_dependencies();
+    for name in resolution.dependencies.keys() {
+        if !all_deps.contains_key(name) {
+            return Err(CliError::LockDrift(format!("{} is locked but not in the manifest", name)));
+        }
+    }
+    let mut res = BTreeMap::new();
+    for (name, req) in reqs {
+        let dep = resolution.dependencies
+            .get(name)
+            .ok_or_else(|| CliError::LockDrift(format!("{} is not locked", name)))?;
+        if !req.matches(&dep.version) {
+            let msg = format!("{} is locked at {}, but the manifest requires {}",
+                              name,
+                              dep.version,
+                              req);
+            return Err(CliError::LockDrift(msg));
+        }
+        let locked_env = dep.environment.clone().unwrap_or_default();
+        if locked_env != env {
+            let msg = format!("{} is locked in the '{}' environment, not '{}'",
+                              name,
+                              locked_env,
+                              env);
+            return Err(CliError::LockDrift(msg));
+        }
+        if dep.sha256.is_none() {
+            return Err(CliError::LockDrift(format!("{} has no locked checksum", name)));
+        }
+        res.insert(name.clone(), dep.version.clone());
+    }
+    Ok(res)
+}
+
+// ensure the tarballs and trees installed with `--locked` are the locked ones
+fn verify_locked(
+    resolution: &Resolution,
+    installed: &BTreeMap<String, Version>,
+    cache: &str,
+    env: &str,
+) -> LalResult<()> {
+    for name in installed.keys() {
+        let locked = &resolution.dependencies[name];
+        let actual = installed_resolution(cache, name, env)?;
+        if actual.sha256 != locked.sha256 {
+            let msg = format!("{}={} has tarball checksum {}, but {} is locked",
+                              name,
+                              actual.version,
+                              actual.sha256.unwrap_or_default(),
+                              locked.sha256.clone().unwrap_or_default());
+            return Err(CliError::LockDrift(msg));
+        }
+        if actual.dependencies != locked.dependencies {
+            let msg = format!("{}={} was built against different dependencies than locked",
+                              name,
+                              actual.version);
+            return Err(CliError::LockDrift(msg));
+        }
+    }
+    Ok(())
+}
+
 /// Fetch all dependencies from `manifest.json`
 ///
 /// This will read, and HTTP GET all the dependencies at the specified versions.
 /// If the `core` bool is set, then `devDependencies` are not installed.
-pub fn fetch<T: CachedBackend + ?Sized>(
+/// Version constraints install the version recorded in `.lal/lock.json`, and are
+/// resolved first if needed.
+/// Up to `jobs` dependencies are downloaded and unpacked at the same time.
+///
+/// The published lockfiles of the dependencies are checked first, and nothing is
+/// downloaded if the full tree has version conflicts, cycles or environment mismatches.
+///
+/// What ends up in `./INPUT` is recorded in `.lal/lock.json`. With `locked`, the
+/// recorded versions are installed instead (without reusing anything in `./INPUT`),
+/// and any difference from the manifest, environment or recorded checksums is an error.
+pub fn fetch<T: CachedBackend + Backend + ?Sized>(
     manifest: &Manifest,
     backend: &T,
     core: bool,
     env: &str,
+    jobs: usize,
+    locked: bool,
 ) -> LalResult<()> {
     // first ensure manifest is sane:
     manifest.verify()?;
@@ -28,12 +127,35 @@ pub fn fetch<T: CachedBackend + ?Sized>(
            if !core { " and devDependencies" } else { "" });
 
     // create the joined hashmap of dependencies and possibly devdependencies
-    let mut deps = manifest.dependencies.clone();
+    let mut reqs = manifest.dependencies.clone();
     if !core {
         for (k, v) in &manifest.devDependencies {
-            deps.insert(k.clone(), *v);
+            reqs.insert(k.clone(), v.clone());
         }
     }
+    // and pick the versions to install
+    let mut resolution = Resolution::read()?;
+    let wanted = if locked {
+        locked_versions(manifest, &resolution, &reqs, env)?
+    } else {
+        let mut res = BTreeMap::new();
+        for (name, req) in reqs {
+            let version = match resolution.version_for(&name, &req) {
+                Some(v) => v,
+                None => {
+                    let v = resolve_version(backend, manifest, &name, &req)?;
+                    info!("Resolved {} {} to {}", name, req, v);
+                    v
+                }
+            };
+            res.insert(name, version);
+        }
+        res
+    };
+    // refuse an inconsistent tree before downloading anything
+    check_published(backend, &manifest.name, &wanted, env)?;
+
+    let mut deps = wanted.clone();
     let mut extraneous = vec![]; // stuff we should remove
 
     // figure out what we have already
@@ -49,10 +171,13 @@ pub fn fetch<T: CachedBackend + ?Sized>(
     // filter out what we already have (being careful to examine env)
     for (name, d) in lf.dependencies {
         // if d.name at d.version in d.environment matches something in deps
-        if let Some(&cand) = deps.get(&name) {
+        if let Some(cand) = deps.get(&name).cloned() {
+            if locked {
+                continue; // reinstall from the verified tarball
+            }
             // version found in manifest
-            // ignore non-integer versions (stashed things must be overwritten)
-            if let Ok(n) = d.version.parse::<u32>() {
+            // ignore stashed versions (stashed things must be overwritten)
+            if let Ok(n) = d.version.parse::<Version>() {
                 if n == cand && d.environment == env {
                     info!("Reuse {} {} {}", env, name, n);
                     deps.remove(&name);
@@ -63,12 +188,9 @@ pub fn fetch<T: CachedBackend + ?Sized>(
         }
     }
 
-    let mut err = None;
-    for (k, v) in deps {
-        info!("Fetch {} {} {}", env, k, v);
-
-        // first kill the folders we actually need to fetch:
-        let cmponent_dir = Path::new("./INPUT").join(&k);
+    // first kill the folders we actually need to fetch:
+    for k in deps.keys() {
+        let cmponent_dir = Path::new("./INPUT").join(k);
         if cmponent_dir.is_dir() {
             // Don't think this can fail, but we are dealing with NFS
             fs::remove_dir_all(&cmponent_dir)
@@ -78,8 +200,13 @@ pub fn fetch<T: CachedBackend + ?Sized>(
                     e
                 })?;
         }
+    }
 
-        let _ = backend.unpack_published_component(&k, Some(v), env).map_err(|e| {
+    let mut err = None;
+    let fetches: Vec<(String, Version)> = deps.into_iter().collect();
+    let results = backend.unpack_published_components(&fetches, env, jobs);
+    for (&(ref k, _), res) in fetches.iter().zip(results) {
+        let _ = res.map_err(|e| {
             warn!("Failed to completely install {} ({})", k, e);
             // likely symlinks inside tarball that are being dodgy
             // this is why we clean_input
@@ -101,5 +228,40 @@ pub fn fetch<T: CachedBackend + ?Sized>(
         clean_input(); // don't want to risk having users in corrupted states
         return Err(CliError::InstallFailure);
     }
+
+    let cache = backend.get_cache_dir();
+    if locked {
+        return verify_locked(&resolution, &wanted, &cache, env).map_err(|e| {
+            warn!("Cleaning INPUT that does not match .lal/lock.json");
+            clean_input();
+            e
+        });
+    }
+
+    // record what is now installed
+    let previous = resolution.clone();
+    let all_deps = manifest.all_dependencies();
+    let removed: Vec<String> = resolution.dependencies
+        .keys()
+        .filter(|name| !all_deps.contains_key(*name))
+        .cloned()
+        .collect();
+    for name in removed {
+        resolution.dependencies.remove(&name);
+    }
+    for name in wanted.keys() {
+        let mut dep = installed_resolution(&cache, name, env)?;
+        if let Some(old) = previous.dependencies.get(name) {
+            // a reused component may no longer be cached
+            if dep.sha256.is_none() && old.version == dep.version &&
+               old.environment == dep.environment {
+                dep.sha256 = old.sha256.clone();
+            }
+        }
+        resolution.dependencies.insert(name.clone(), dep);
+    }
+    if resolution != previous {
+        resolution.write()?;
+    }
     Ok(())
 }
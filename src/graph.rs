@@ -0,0 +1,143 @@
+use serde_json;
+use std::collections::BTreeSet;
+
+use super::{LalResult, CliError, Manifest, Lockfile};
+
+/// A component in the dependency graph
+#[derive(Serialize)]
+pub struct GraphNode {
+    /// Name of the component
+    pub name: String,
+    /// Versions it is used at (empty for the root)
+    pub versions: Vec<String>,
+    /// Environments it was built in (empty for the root)
+    pub environments: Vec<String>,
+    /// Whether several versions of it are used in the tree
+    pub conflict: bool,
+}
+
+/// A dependency between two components in the graph
+#[derive(Serialize)]
+pub struct GraphEdge {
+    /// The dependent component
+    pub from: String,
+    /// The component it depends on
+    pub to: String,
+}
+
+/// A deduplicated dependency graph with one node per component
+#[derive(Serialize)]
+pub struct Graph {
+    /// Name of the root component
+    pub root: String,
+    /// Components sorted by name (after the root)
+    pub nodes: Vec<GraphNode>,
+    /// Dependencies sorted by dependent and name
+    pub edges: Vec<GraphEdge>,
+}
+
+/// Compute the dependency graph of a populated lockfile
+pub fn compute(lf: &Lockfile) -> Graph {
+    let names = lf.find_all_dependency_names();
+    let versions = lf.find_all_dependency_versions();
+    let envs = lf.find_all_environments();
+
+    let mut nodes = vec![GraphNode {
+        name: lf.name.clone(),
+        versions: vec![],
+        environments: vec![],
+        conflict: false,
+    }];
+    let mut edges = vec![];
+    let sorted: BTreeSet<_> = names.keys().cloned().collect();
+    for name in sorted {
+        for dep in &names[&name] {
+            edges.push(GraphEdge {
+                from: name.clone(),
+                to: dep.clone(),
+            });
+        }
+        if name == lf.name {
+            continue;
+        }
+        let vers = versions.get(&name).cloned().unwrap_or_default();
+        let node_envs = envs.get(&name).cloned().unwrap_or_default();
+        nodes.push(GraphNode {
+            conflict: vers.len() > 1,
+            versions: vers.into_iter().collect(),
+            environments: node_envs.into_iter().collect(),
+            name: name,
+        });
+    }
+    Graph {
+        root: lf.name.clone(),
+        nodes: nodes,
+        edges: edges,
+    }
+}
+
+// node label like `ciscossl 41, 42 (xenial)`
+fn label(node: &GraphNode, sep: &str) -> String {
+    if node.versions.is_empty() {
+        return node.name.clone();
+    }
+    format!("{}{}{} ({})",
+            node.name,
+            sep,
+            node.versions.join(", "),
+            node.environments.join(", "))
+}
+
+/// Render a graph in the graphviz DOT language
+pub fn to_dot(g: &Graph) -> String {
+    let mut res = format!("digraph \"{}\" {{\n", g.root);
+    for n in &g.nodes {
+        let style = if n.name == g.root {
+            ", shape=box"
+        } else if n.conflict {
+            ", color=red, fontcolor=red"
+        } else {
+            ""
+        };
+        res.push_str(&format!("  \"{}\" [label=\"{}\"{}];\n", n.name, label(n, "\\n"), style));
+    }
+    for e in &g.edges {
+        res.push_str(&format!("  \"{}\" -> \"{}\";\n", e.from, e.to));
+    }
+    res.push_str("}");
+    res
+}
+
+/// Render a graph as a mermaid flowchart
+pub fn to_mermaid(g: &Graph) -> String {
+    // index based ids as component names are not always valid mermaid ids
+    let id = |name: &str| g.nodes.iter().position(|n| n.name == name).unwrap_or(0);
+    let mut res = String::from("graph TD\n");
+    for (i, n) in g.nodes.iter().enumerate() {
+        let class = if n.conflict { ":::conflict" } else { "" };
+        res.push_str(&format!("  n{}[\"{}\"]{}\n", i, label(n, " "), class));
+    }
+    for e in &g.edges {
+        res.push_str(&format!("  n{} --> n{}\n", id(&e.from), id(&e.to)));
+    }
+    res.push_str("  classDef conflict stroke:#f00,stroke-width:2px,color:#f00");
+    res
+}
+
+/// Prints the dependency graph of `./INPUT`
+///
+/// Every component appears once, labelled with the version(s) and environment(s)
+/// it is used at, and components used at several versions are flagged.
+/// The `format` is one of `dot`, `mermaid` or `json`.
+pub fn print(manifest: &Manifest, format: &str) -> LalResult<()> {
+    let lf = Lockfile::default().set_name(&manifest.name).populate_from_input()?;
+    let g = compute(&lf);
+    let output = match format {
+        "dot" => to_dot(&g),
+        "mermaid" => to_mermaid(&g),
+        "json" => serde_json::to_string_pretty(&g)?,
+        _ => return Err(CliError::InvalidGraphFormat(format.into())),
+    };
+    println!("{}", output);
+    Ok(())
+}
@@ -20,7 +20,9 @@
 #[macro_use]
 extern crate hyper;
 extern crate hyper_native_tls;
+extern crate openssl;
 extern crate openssl_probe;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
@@ -36,6 +38,8 @@ extern crate chrono;
 extern crate filetime;
 extern crate rand;
 extern crate semver;
+extern crate crossbeam;
+extern crate libc;
 #[cfg(feature = "progress")]
 extern crate indicatif;
 
@@ -48,15 +52,27 @@ pub use storage::*;
 
 /// Env module for env subcommand (which has further subcommands)
 pub mod env;
+/// Cache module for the cache subcommand (which has further subcommands)
+pub mod cache;
 /// List module for all the list-* subcommands
 pub mod list;
 /// Propagation module with all structs describing the steps
 pub mod propagate;
+/// Resolver checking the transitive dependency tree before installing anything
+pub mod resolve;
+/// Graph module for exporting the dependency graph
+pub mod graph;
+/// Why module explaining the chains of dependencies pulling in a component
+pub mod why;
+/// Outdated module comparing the dependencies to the latest published versions
+pub mod outdated;
+/// Build cache for restoring `OUTPUT` of builds whose inputs have not changed
+pub mod buildcache;
 
 
 // lift most other pub functions into our libraries main scope
 // this avoids having to type lal::build::build in tests and main.rs
-pub use build::{build, BuildOptions};
+pub use build::{build, build_all, build_combinations, BuildOptions};
 pub use configure::configure;
 pub use init::init;
 pub use shell::{shell, docker_run, script, DockerRunFlags, ShellModes};
@@ -65,11 +81,13 @@ pub use update::{update, update_all};
 pub use remove::remove;
 pub use export::export;
 pub use status::status;
-pub use verify::verify;
+pub use verify::{verify, verify_digests};
 pub use stash::stash;
-pub use clean::clean;
+pub use clean::{clean, parse_size, format_size};
 pub use query::query;
 pub use publish::publish;
+pub use why::why;
+pub use outdated::outdated;
 
 mod configure;
 mod init;
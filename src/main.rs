@@ -10,6 +10,7 @@ use lal::*;
 use clap::{Arg, App, AppSettings, SubCommand, ArgMatches};
 use std::process;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 
 fn is_integer(v: String) -> Result<(), String> {
     if v.parse::<u32>().is_ok() {
@@ -18,6 +19,10 @@ fn is_integer(v: String) -> Result<(), String> {
     Err(format!("{} is not an integer", v))
 }
 
+fn is_size(v: String) -> Result<(), String> {
+    lal::parse_size(&v).map(|_| ()).map_err(|e| e.to_string())
+}
+
 fn result_exit<T>(name: &str, x: LalResult<T>) {
     let _ = x.map_err(|e| {
         println!(""); // add a separator
@@ -44,11 +49,26 @@ fn handle_manifest_agnostic_cmds(
         lal::query(backend,
                    explicit_env,
                    a.value_of("component").unwrap(),
-                   a.is_present("latest"))
+                   a.is_present("latest"),
+                   a.is_present("lockfile"))
     } else if let Some(a) = args.subcommand_matches("publish") {
         lal::publish(a.value_of("component").unwrap(), backend)
     } else if args.subcommand_matches("list-environments").is_some() {
         lal::list::environments(cfg)
+    } else if let Some(a) = args.subcommand_matches("propagate") {
+        // only a propagation through a given tree can be planned outside a repo
+        let xs = a.values_of("components").unwrap().map(String::from).collect::<Vec<_>>();
+        let tree = if let Some(path) = a.value_of("lockfile") {
+            Lockfile::from_path(&PathBuf::from(path), "lockfile")
+        } else if let Some(component) = a.value_of("component") {
+            match a.value_of("env").or(explicit_env) {
+                Some(e) => lal::propagate::published_component_tree(backend, component, e),
+                None => Err(CliError::EnvironmentUnspecified),
+            }
+        } else {
+            return ();
+        };
+        tree.and_then(|lf| lal::propagate::print_for(&lf, &xs, a.is_present("json")))
     } else {
         return ();
     };
@@ -73,17 +93,27 @@ fn handle_environment_agnostic_cmds(args: &ArgMatches, mf: &Manifest, backend: &
     } else if let Some(a) = args.subcommand_matches("remove") {
         let xs = a.values_of("components").unwrap().map(String::from).collect::<Vec<_>>();
         lal::remove(mf, xs, a.is_present("save"), a.is_present("savedev"))
+    } else if let Some(a) = args.subcommand_matches("graph") {
+        lal::graph::print(mf, a.value_of("format").unwrap())
     } else if let Some(a) = args.subcommand_matches("stash") {
         lal::stash(backend, mf, a.value_of("name").unwrap())
-    } else if let Some(a) = args.subcommand_matches("propagate") {
-        lal::propagate::print(mf, a.value_of("component").unwrap(), a.is_present("json"))
     } else {
         return ();
     };
     result_exit(args.subcommand_name().unwrap(), res);
 }
 
-fn handle_network_cmds(args: &ArgMatches, mf: &Manifest, backend: &Backend, env: &str) {
+fn handle_network_cmds(
+    args: &ArgMatches,
+    cfg: &Config,
+    mf: &Manifest,
+    backend: &Backend,
+    env: &str,
+) {
+    // parallel downloads from -j or the config
+    let jobs = |a: &ArgMatches| {
+        a.value_of("jobs").map(|j| j.parse().unwrap()).unwrap_or(cfg.jobs)
+    };
     let res = if let Some(a) = args.subcommand_matches("update") {
         let xs = a.values_of("components").unwrap().map(String::from).collect::<Vec<_>>();
         lal::update(mf,
@@ -91,11 +121,38 @@ fn handle_network_cmds(args: &ArgMatches, mf: &Manifest, backend: &Backend, env:
                     xs,
                     a.is_present("save"),
                     a.is_present("savedev"),
-                    env)
+                    env,
+                    jobs(a))
     } else if let Some(a) = args.subcommand_matches("update-all") {
-        lal::update_all(mf, backend, a.is_present("save"), a.is_present("dev"), env)
+        lal::update_all(mf,
+                        backend,
+                        a.is_present("save"),
+                        a.is_present("dev"),
+                        env,
+                        jobs(a))
     } else if let Some(a) = args.subcommand_matches("fetch") {
-        lal::fetch(mf, backend, a.is_present("core"), env)
+        lal::fetch(mf,
+                   backend,
+                   a.is_present("core"),
+                   env,
+                   jobs(a),
+                   a.is_present("locked"))
+    } else if let Some(a) = args.subcommand_matches("outdated") {
+        lal::outdated(mf, backend, env, a.is_present("json"))
+    } else if let Some(a) = args.subcommand_matches("why") {
+        lal::why(mf,
+                 backend,
+                 env,
+                 a.value_of("component").unwrap(),
+                 a.is_present("json"))
+    } else if let Some(a) = args.subcommand_matches("propagate") {
+        let xs = a.values_of("components").unwrap().map(String::from).collect::<Vec<_>>();
+        if a.is_present("execute") {
+            let workspace = Path::new(a.value_of("workspace").unwrap());
+            lal::propagate::execute(mf, backend, env, &xs, workspace, a.is_present("dry-run"))
+        } else {
+            lal::propagate::print(mf, backend, env, &xs, a.is_present("json"))
+        }
     } else {
         return (); // not a network cmnd
     };
@@ -121,7 +178,7 @@ fn handle_env_command(
     // resolve env updates and sticky options before main subcommands
     if let Some(a) = args.subcommand_matches("env") {
         if a.subcommand_matches("update").is_some() {
-            result_exit("env update", lal::env::update(&container, env))
+            result_exit("env update", lal::env::update(cfg, &container, env))
         } else if a.subcommand_matches("reset").is_some() {
             // NB: if .lal/opts.env points at an environment not in config
             // reset will fail.. possible to fix, but complects this file too much
@@ -147,6 +204,14 @@ fn handle_upgrade(args: &ArgMatches, cfg: &Config) {
     // we have a subcommand because SubcommandRequiredElseHelp
     let subname = args.subcommand_name().unwrap();
 
+    // No upgrade checks without network access
+    if args.is_present("offline") {
+        if subname == "upgrade" {
+            result_exit::<()>("upgrade", Err(CliError::OfflineOperation("upgrade".into())));
+        }
+        return;
+    }
+
     // Allow lal upgrade without manifest
     if args.subcommand_matches("upgrade").is_some() {
         result_exit("upgrade", lal::upgrade(false)); // explicit, verbose check
@@ -182,7 +247,12 @@ fn handle_docker_cmds(
     let res = if let Some(a) = args.subcommand_matches("verify") {
         // not really a docker related command, but it needs
         // the resolved env to verify consistent dependency usage
-        lal::verify(mf, env, a.is_present("simple"))
+        let res = lal::verify(mf, env, a.is_present("simple"));
+        if res.is_ok() && a.is_present("digests") {
+            lal::verify_digests(env)
+        } else {
+            res
+        }
     } else if let Some(a) = args.subcommand_matches("build") {
         let bopts = BuildOptions {
             name: a.value_of("component").map(String::from),
@@ -193,14 +263,23 @@ fn handle_docker_cmds(
             container: container.clone(),
             force: a.is_present("force"),
             simple_verify: a.is_present("simple-verify"),
+            cache: !a.is_present("no-cache"),
         };
         let modes = ShellModes {
             printonly: a.is_present("print"),
             x11_forwarding: a.is_present("x11"),
             host_networking: a.is_present("net-host"),
             env_vars: values_t!(a.values_of("env-var"), String).unwrap_or(vec![]),
+            native: a.is_present("no-container"),
         };
-        lal::build(cfg, mf, &bopts, env.into(), modes)
+        if a.is_present("all") || a.is_present("all-configurations") {
+            let jobs = a.value_of("jobs").map(|j| j.parse().unwrap()).unwrap_or(1);
+            lal::build_combinations(mf, a.value_of("component")).and_then(|combos| {
+                lal::build_all(cfg, mf, &bopts, &combos, env.into(), modes, jobs)
+            })
+        } else {
+            lal::build(cfg, mf, &bopts, env.into(), modes)
+        }
     } else if let Some(a) = args.subcommand_matches("shell") {
         let xs = if a.is_present("cmd") {
             Some(a.values_of("cmd").unwrap().collect::<Vec<_>>())
@@ -212,6 +291,7 @@ fn handle_docker_cmds(
             x11_forwarding: a.is_present("x11"),
             host_networking: a.is_present("net-host"),
             env_vars: values_t!(a.values_of("env-var"), String).unwrap_or(vec![]),
+            native: a.is_present("no-container"),
         };
         lal::shell(cfg, container, &modes, xs, a.is_present("privileged"))
     } else if let Some(a) = args.subcommand_matches("run") {
@@ -225,6 +305,7 @@ fn handle_docker_cmds(
             x11_forwarding: a.is_present("x11"),
             host_networking: a.is_present("net-host"),
             env_vars: values_t!(a.values_of("env-var"), String).unwrap_or(vec![]),
+            native: a.is_present("no-container"),
         };
         lal::script(cfg,
                     container,
@@ -260,12 +341,24 @@ fn main() {
             .short("d")
             .long("debug")
             .help("Adds line numbers to log statements"))
+        .arg(Arg::with_name("offline")
+            .long("offline")
+            .help("Only use components already in the cache (no network access)"))
         .subcommand(SubCommand::with_name("fetch")
             .about("Fetch dependencies listed in the manifest into INPUT")
             .arg(Arg::with_name("core")
                 .long("core")
                 .short("c")
-                .help("Only fetch core dependencies")))
+                .help("Only fetch core dependencies"))
+            .arg(Arg::with_name("locked")
+                .long("locked")
+                .help("Fetch exactly what is recorded in .lal/lock.json (fail on any drift)"))
+            .arg(Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .validator(is_integer)
+                .help("Number of parallel downloads (defaults to jobs in the config)")))
         .subcommand(SubCommand::with_name("build")
             .about("Runs BUILD script in current directory in the configured container")
             .arg(Arg::with_name("component")
@@ -314,7 +407,28 @@ fn main() {
             .arg(Arg::with_name("print")
                 .long("print-only")
                 .conflicts_with("release")
-                .help("Only print the docker run command and exit")))
+                .help("Only print the docker run command and exit"))
+            .arg(Arg::with_name("no-container")
+                .long("no-container")
+                .help("Run directly on the host instead of in a container"))
+            .arg(Arg::with_name("no-cache")
+                .long("no-cache")
+                .help("Always run the build rather than restoring it from the build cache"))
+            .arg(Arg::with_name("all")
+                .long("all")
+                .conflicts_with_all(&["component", "configuration", "release", "no-container"])
+                .help("Build every configuration of every component, each into OUTPUT/<c>/<cfg>"))
+            .arg(Arg::with_name("all-configurations")
+                .long("all-configurations")
+                .requires("component")
+                .conflicts_with_all(&["configuration", "release", "no-container"])
+                .help("Build every configuration of the component, each into OUTPUT/<cfg>"))
+            .arg(Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .validator(is_integer)
+                .help("Number of builds to run in parallel with --all or --all-configurations")))
         .subcommand(SubCommand::with_name("update")
             .about("Update arbitrary dependencies into INPUT")
             .arg(Arg::with_name("components")
@@ -330,12 +444,21 @@ fn main() {
                 .short("D")
                 .long("save-dev")
                 .conflicts_with("save")
-                .help("Save updated versions in devDependencies in the manifest")))
+                .help("Save updated versions in devDependencies in the manifest"))
+            .arg(Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .validator(is_integer)
+                .help("Number of parallel downloads (defaults to jobs in the config)")))
         .subcommand(SubCommand::with_name("verify")
             .arg(Arg::with_name("simple")
                 .short("s")
                 .long("simple")
                 .help("Allow stashed versions in this simpler verify algorithm"))
+            .arg(Arg::with_name("digests")
+                .long("digests")
+                .help("Warn about dependencies built with different images of the environment"))
             .about("verify consistency of INPUT"))
         .subcommand(SubCommand::with_name("status")
             .alias("ls")
@@ -376,6 +499,9 @@ fn main() {
             .arg(Arg::with_name("print")
                 .long("print-only")
                 .help("Only print the docker run command and exit"))
+            .arg(Arg::with_name("no-container")
+                .long("no-container")
+                .help("Run directly on the host instead of in a container"))
             .setting(AppSettings::TrailingVarArg)
             .arg(Arg::with_name("cmd").multiple(true)))
         .subcommand(SubCommand::with_name("run")
@@ -401,6 +527,9 @@ fn main() {
             .arg(Arg::with_name("print")
                 .long("print-only")
                 .help("Only print the docker run command and exit"))
+            .arg(Arg::with_name("no-container")
+                .long("no-container")
+                .help("Run directly on the host instead of in a container"))
             .arg(Arg::with_name("privileged")
                 .short("p")
                 .long("privileged")
@@ -472,25 +601,134 @@ fn main() {
                 .takes_value(true)
                 .default_value("14")
                 .validator(is_integer)
-                .help("Number of days to serve as cutoff")))
+                .help("Number of days to serve as cutoff"))
+            .arg(Arg::with_name("max-size")
+                .long("max-size")
+                .takes_value(true)
+                .validator(is_size)
+                .help("Evict least recently used artifacts until the cache fits (e.g. 20G)"))
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only print what would be deleted and how much space it frees")))
+        .subcommand(SubCommand::with_name("cache")
+            .about("Inspects and maintains the cache directory")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("verify")
+                .about("Re-hash all cached tarballs and quarantine corrupted ones"))
+            .subcommand(SubCommand::with_name("ls")
+                .about("List cached components and stashed builds with sizes and last use")
+                .arg(Arg::with_name("json")
+                    .long("json")
+                    .help("Produce machine readable output")))
+            .subcommand(SubCommand::with_name("rm")
+                .about("Remove components from the cache")
+                .arg(Arg::with_name("component")
+                    .required(true)
+                    .help("Component name, optionally with =version or =stashname"))
+                .arg(Arg::with_name("env")
+                    .long("env")
+                    .takes_value(true)
+                    .help("Only remove versions cached for this environment")))
+            .subcommand(SubCommand::with_name("import")
+                .about("Add a tarball to the cache as a published component")
+                .arg(Arg::with_name("tarball")
+                    .required(true)
+                    .help("Path to the tarball"))
+                .arg(Arg::with_name("component")
+                    .required(true)
+                    .help("Component name with =version"))
+                .arg(Arg::with_name("env")
+                    .long("env")
+                    .takes_value(true)
+                    .help("Environment the tarball was built in")))
+            .subcommand(SubCommand::with_name("path")
+                .about("Print the location of a cached tarball")
+                .arg(Arg::with_name("component")
+                    .required(true)
+                    .help("Component name with =version or =stashname"))
+                .arg(Arg::with_name("env")
+                    .long("env")
+                    .takes_value(true)
+                    .help("Only show the tarball cached for this environment"))
+                .arg(Arg::with_name("json")
+                    .long("json")
+                    .help("Produce machine readable output"))))
         .subcommand(SubCommand::with_name("query")
             .about("Query for available versions on artifactory")
             .arg(Arg::with_name("latest")
                 .long("latest")
                 .short("l")
                 .help("Return latest version only"))
+            .arg(Arg::with_name("lockfile")
+                .long("lockfile")
+                .conflicts_with("latest")
+                .help("Print the published lockfile of the latest or a given version"))
             .arg(Arg::with_name("component")
                 .required(true)
-                .help("Component name to search for")))
+                .help("Component name to search for (with =version for --lockfile)")))
         .subcommand(SubCommand::with_name("propagate")
             .about("Show steps to propagate a version fully through the tree")
+            .arg(Arg::with_name("components")
+                .required(true)
+                .multiple(true)
+                .help("Components to propagate"))
+            .arg(Arg::with_name("json")
+                .short("j")
+                .long("json")
+                .conflicts_with("execute")
+                .help("Produce a machine readable instruction set"))
+            .arg(Arg::with_name("execute")
+                .long("execute")
+                .requires("workspace")
+                .help("Update, build and publish every repo in the workspace"))
+            .arg(Arg::with_name("workspace")
+                .long("workspace")
+                .takes_value(true)
+                .requires("execute")
+                .help("Directory with a checkout of every repo to update"))
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .requires("execute")
+                .help("Print the commands of the propagation without running them"))
+            .arg(Arg::with_name("lockfile")
+                .long("lockfile")
+                .takes_value(true)
+                .conflicts_with_all(&["execute", "component"])
+                .help("Propagate through the tree of a lockfile instead of the current repo"))
+            .arg(Arg::with_name("component")
+                .long("component")
+                .takes_value(true)
+                .conflicts_with("execute")
+                .help("Propagate through the tree of a published component=version"))
+            .arg(Arg::with_name("env")
+                .long("env")
+                .takes_value(true)
+                .requires("component")
+                .help("Environment the --component was published in")))
+        .subcommand(SubCommand::with_name("graph")
+            .about("Print the dependency graph of INPUT")
+            .arg(Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["dot", "mermaid", "json"])
+                .default_value("dot")
+                .help("Output format")))
+        .subcommand(SubCommand::with_name("outdated")
+            .about("Compare dependencies to the latest available versions")
+            .arg(Arg::with_name("json")
+                .short("j")
+                .long("json")
+                .help("Produce machine readable output")))
+        .subcommand(SubCommand::with_name("why")
+            .about("Show every chain of dependencies pulling in a component")
             .arg(Arg::with_name("component")
                 .required(true)
-                .help("Component to propagate"))
+                .help("Component name, optionally with =version"))
             .arg(Arg::with_name("json")
                 .short("j")
                 .long("json")
-                .help("Produce a machine readable instruction set")))
+                .help("Produce machine readable output")))
         .subcommand(SubCommand::with_name("update-all")
             .about("Update all dependencies in the manifest")
             .arg(Arg::with_name("dev")
@@ -500,7 +738,13 @@ fn main() {
             .arg(Arg::with_name("save")
                 .short("S")
                 .long("save")
-                .help("Save updated versions in the right object in the manifest")))
+                .help("Save updated versions in the right object in the manifest"))
+            .arg(Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .validator(is_integer)
+                .help("Number of parallel downloads (defaults to jobs in the config)")))
         .subcommand(SubCommand::with_name("publish")
             .setting(AppSettings::Hidden)
             .arg(Arg::with_name("component")
@@ -564,13 +808,10 @@ fn main() {
         .unwrap();
 
     // Create a storage backend (something that implements storage/traits.rs)
-    let backend: Box<Backend> = match &config.backend {
-        &BackendConfiguration::Artifactory(ref art_cfg) => {
-            Box::new(ArtifactoryBackend::new(&art_cfg, &config.cache))
-        }
-        &BackendConfiguration::Local(ref local_cfg) => {
-            Box::new(LocalBackend::new(&local_cfg, &config.cache))
-        }
+    let backend: Box<Backend> = if args.is_present("offline") {
+        Box::new(OfflineBackend::new(&config.cache))
+    } else {
+        config.backend.to_backend(&config.cache)
     };
 
     // Ensure SSL is initialized before using the backend
@@ -587,7 +828,41 @@ fn main() {
                               a.value_of("environment").unwrap()));
     } else if let Some(a) = args.subcommand_matches("clean") {
         let days = a.value_of("days").unwrap().parse().unwrap();
-        result_exit("clean", lal::clean(&config.cache, days));
+        // explicit --max-size overrides any configured limit
+        let max_size = a.value_of("max-size").map(String::from).or(config.cache_max_size.clone());
+        let res = max_size.map_or(Ok(None), |s| lal::parse_size(&s).map(Some))
+            .and_then(|size| lal::clean(&config.cache, days, size, a.is_present("dry-run")));
+        result_exit("clean", res);
+    } else if let Some(a) = args.subcommand_matches("cache") {
+        let cache = &config.cache;
+        let global_env = args.value_of("environment");
+        if a.subcommand_matches("verify").is_some() {
+            result_exit("cache verify", lal::cache::verify(cache));
+        } else if let Some(sa) = a.subcommand_matches("ls") {
+            result_exit("cache ls", lal::cache::ls(cache, sa.is_present("json")));
+        } else if let Some(sa) = a.subcommand_matches("rm") {
+            // --env on the subcommand, or the usual global -e
+            let env = sa.value_of("env").or(global_env);
+            result_exit("cache rm",
+                        lal::cache::rm(cache, sa.value_of("component").unwrap(), env));
+        } else if let Some(sa) = a.subcommand_matches("import") {
+            let res = match sa.value_of("env").or(global_env) {
+                Some(e) => {
+                    lal::cache::import(cache,
+                                       sa.value_of("tarball").unwrap(),
+                                       sa.value_of("component").unwrap(),
+                                       e)
+                }
+                None => Err(CliError::EnvironmentUnspecified),
+            };
+            result_exit("cache import", res);
+        } else if let Some(sa) = a.subcommand_matches("path") {
+            result_exit("cache path",
+                        lal::cache::path(cache,
+                                         sa.value_of("component").unwrap(),
+                                         sa.value_of("env").or(global_env),
+                                         sa.is_present("json")));
+        }
     }
 
     // Read .lal/opts if it exists
@@ -646,7 +921,7 @@ fn main() {
     }
 
     // Main subcommands
-    handle_network_cmds(&args, &manifest, backend.deref(), &env);
+    handle_network_cmds(&args, &config, &manifest, backend.deref(), &env);
     handle_docker_cmds(&args, &manifest, &config, &env, &container);
 
     unreachable!("Subcommand valid, but not implemented");
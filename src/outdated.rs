@@ -0,0 +1,164 @@
+use std::collections::BTreeSet;
+use ansi_term::Colour;
+use serde_json;
+
+use storage::CachedBackend;
+use core::input;
+use super::{LalResult, Manifest, Lockfile, Version, Resolution};
+use propagate::manifest_tree;
+use resolve::{problems, Problem};
+
+/// How a dependency in the manifest compares to what is available
+#[derive(Serialize)]
+pub struct OutdatedDependency {
+    /// Name of the dependency
+    pub name: String,
+    /// Whether it is a devDependency
+    pub development: bool,
+    /// Version or constraint in the manifest
+    pub requirement: String,
+    /// Version in `./INPUT` (if installed)
+    pub input: Option<String>,
+    /// Latest version available in all the `supportedEnvironments`
+    pub latest: Option<Version>,
+    /// Whether the latest version is newer than the one in use
+    pub outdated: bool,
+    /// Components that would be used at multiple versions after upgrading to latest
+    pub breaks: Vec<String>,
+}
+
+// components used at several versions in a tree
+fn conflicts(lf: &Lockfile, env: &str) -> BTreeSet<String> {
+    problems(lf, env)
+        .into_iter()
+        .filter_map(|p| match p {
+            Problem::Conflict(name, _) => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compare every dependency in the manifest to the latest available versions
+///
+/// Upgrades are checked against the published lockfile of the latest version, so that
+/// upgrades that would break the flat dependency tree (as `lal verify` requires) are found
+/// without downloading anything.
+pub fn compute<T: CachedBackend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    env: &str,
+) -> LalResult<Vec<OutdatedDependency>> {
+    let installed = input::analyze()?;
+    let resolution = Resolution::read()?;
+    let mut tree = manifest_tree(manifest, backend, env)?;
+    let existing = conflicts(&tree, env);
+
+    let mut res = vec![];
+    for (name, req) in manifest.all_dependencies() {
+        let input = installed.get(&name).cloned();
+        let latest = match backend
+            .get_latest_supported_versions(&name, manifest.supportedEnvironments.clone()) {
+            Ok(versions) => versions.into_iter().max(),
+            Err(e) => {
+                warn!("Failed to find the latest version of {} ({})", name, e);
+                None
+            }
+        };
+        let current = resolution
+            .version_for(&name, &req)
+            .or_else(|| input.as_ref().and_then(|v| v.parse::<Version>().ok()));
+        let outdated = match (latest.as_ref(), current.as_ref()) {
+            (Some(l), Some(c)) => l > c,
+            (Some(_), None) => true,
+            _ => false,
+        };
+
+        let mut breaks = vec![];
+        if outdated {
+            let newest = latest.clone().unwrap();
+            match backend.retrieve_published_lockfile(&name, &newest, env) {
+                Ok(lf) => {
+                    // swap the upgrade into the tree, and see what no longer agrees
+                    let old = tree.dependencies.insert(name.clone(), lf);
+                    breaks = conflicts(&tree, env).difference(&existing).cloned().collect();
+                    match old {
+                        Some(o) => tree.dependencies.insert(name.clone(), o),
+                        None => tree.dependencies.remove(&name),
+                    };
+                }
+                Err(e) => warn!("Unable to check upgrading {} to {} ({})", name, newest, e),
+            }
+        }
+
+        res.push(OutdatedDependency {
+            development: manifest.devDependencies.contains_key(&name),
+            requirement: req.to_string(),
+            name: name,
+            input: input,
+            latest: latest,
+            outdated: outdated,
+            breaks: breaks,
+        });
+    }
+    Ok(res)
+}
+
+/// Prints how the dependencies in the manifest compare to the latest available versions
+///
+/// For every dependency and devDependency, this shows the version in the manifest,
+/// the version in `./INPUT`, and the latest version available in all the
+/// `supportedEnvironments`. Upgrades that would break the flat dependency tree are flagged.
+pub fn outdated<T: CachedBackend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    env: &str,
+    json_output: bool,
+) -> LalResult<()> {
+    let deps = compute(manifest, backend, env)?;
+
+    if json_output {
+        let encoded = serde_json::to_string_pretty(&deps)?;
+        println!("{}", encoded);
+        return Ok(());
+    }
+
+    let rows: Vec<(String, String, String)> = deps.iter()
+        .map(|d| {
+            let name = if d.development { format!("{} (dev)", d.name) } else { d.name.clone() };
+            let input = d.input.clone().unwrap_or_else(|| "-".into());
+            (name, d.requirement.clone(), input)
+        })
+        .collect();
+    let w0 = rows.iter().map(|r| r.0.len()).chain(Some(4)).max().unwrap();
+    let w1 = rows.iter().map(|r| r.1.len()).chain(Some(8)).max().unwrap();
+    let w2 = rows.iter().map(|r| r.2.len()).chain(Some(5)).max().unwrap();
+
+    println!("{:w0$}  {:w1$}  {:w2$}  {}",
+             "Name",
+             "Manifest",
+             "Input",
+             "Latest",
+             w0 = w0,
+             w1 = w1,
+             w2 = w2);
+    for (d, r) in deps.iter().zip(&rows) {
+        let latest = d.latest.as_ref().map(|v| v.to_string()).unwrap_or_else(|| "-".into());
+        let latest = if d.outdated { Colour::Yellow.paint(latest).to_string() } else { latest };
+        let note = if d.breaks.is_empty() {
+            String::new()
+        } else {
+            let msg = format!(" (upgrade breaks flat tree: {})", d.breaks.join(", "));
+            Colour::Red.paint(msg).to_string()
+        };
+        println!("{:w0$}  {:w1$}  {:w2$}  {}{}",
+                 r.0,
+                 r.1,
+                 r.2,
+                 latest,
+                 note,
+                 w0 = w0,
+                 w1 = w1,
+                 w2 = w2);
+    }
+    Ok(())
+}
@@ -1,10 +1,18 @@
 use serde_json;
-use std::collections::BTreeSet;
-use super::{LalResult, Manifest, Lockfile};
+use std::env;
+use std::fs;
+use std::io::prelude::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::collections::{BTreeMap, BTreeSet};
+use storage::{Backend, CachedBackend};
+use super::{CliError, LalResult, Manifest, Lockfile, Resolution, Version, input};
+use resolve::published_tree;
+use update::resolve_version;
 
 
 /// A single update of of a propagation
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SingleUpdate {
     /// Where to update dependencies
     pub repo: String,
@@ -13,14 +21,14 @@ pub struct SingleUpdate {
 }
 
 /// A parallelizable update stage of a propagation
-#[derive(Serialize, Default)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct UpdateStage {
     /// Updates to perform at this stage
     pub updates: Vec<SingleUpdate>,
 }
 
 /// A set of sequential update steps that describe a propagation
-#[derive(Serialize, Default)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct UpdateSequence {
     /// Update stages needed
     pub stages: Vec<UpdateStage>,
@@ -28,8 +36,22 @@ pub struct UpdateSequence {
 
 /// Compute the update sequence for a propagation
 pub fn compute(lf: &Lockfile, component: &str) -> LalResult<UpdateSequence> {
-    // 1. collect the list of everything we want to build in between root and component
-    let all_required = lf.get_reverse_deps_transitively_for(component.into());
+    compute_many(lf, &[component.to_string()])
+}
+
+/// Compute a single update sequence for propagating several components at once
+///
+/// All the components are assumed to have been updated already, so they are never
+/// updated themselves, even when one of them depends on another.
+pub fn compute_many(lf: &Lockfile, components: &[String]) -> LalResult<UpdateSequence> {
+    // 1. collect the list of everything we want to build in between root and components
+    let mut all_required = BTreeSet::new();
+    for component in components {
+        all_required.extend(lf.get_reverse_deps_transitively_for(component.clone()));
+    }
+    for component in components {
+        all_required.remove(component);
+    }
     let dependencies = lf.find_all_dependency_names(); // map String -> Set(names)
 
     debug!("Needs updating: {:?}", all_required);
@@ -38,8 +60,8 @@ pub fn compute(lf: &Lockfile, component: &str) -> LalResult<UpdateSequence> {
     // initialize mutables
     let mut result = UpdateSequence::default();
     let mut remaining = all_required.clone();
-    // assume we already updated the component itself
-    let mut handled = vec![component.to_string()].into_iter().collect();
+    // assume we already updated the components themselves
+    let mut handled: BTreeSet<String> = components.iter().cloned().collect();
 
     // create update stages while there is something left to update
     while !remaining.is_empty() {
@@ -76,26 +98,44 @@ pub fn compute(lf: &Lockfile, component: &str) -> LalResult<UpdateSequence> {
 }
 
 
-/// Outputs the update path to the current manifest for a specific component
+/// The dependency tree of the current manifest
 ///
-/// Given a component to propagate to the current one in your working directory,
-/// work out how to propagate it through the dependency tree fully.
+/// This is read from `./INPUT` if it has been fetched, and is otherwise built from the
+/// published lockfiles of the dependencies without downloading any tarballs.
+pub fn manifest_tree<T: CachedBackend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    env: &str,
+) -> LalResult<Lockfile> {
+    if input::present() {
+        return Lockfile::default().set_name(&manifest.name).populate_from_input();
+    }
+    let resolution = Resolution::read()?;
+    let mut deps = BTreeMap::new();
+    for (name, req) in manifest.all_dependencies() {
+        let version = match resolution.version_for(&name, &req) {
+            Some(v) => v,
+            None => resolve_version(backend, manifest, &name, &req)?,
+        };
+        deps.insert(name, version);
+    }
+    Ok(published_tree(backend, &manifest.name, &deps, env))
+}
+
+/// Outputs the update path for propagating components through a dependency tree
 ///
 /// This will produce a set of sequential steps, each set itself being parallelizable.
 /// The resulting update steps can be performed in order to ensure `lal verify` is happy.
-pub fn print(manifest: &Manifest, component: &str, json_output: bool) -> LalResult<()> {
-    debug!("Calculating update path for {}", component);
-
-    // TODO: allow taking a custom lockfile to be used outside a repo.
-    let lf = Lockfile::default().set_name(&manifest.name).populate_from_input()?;
+pub fn print_for(lf: &Lockfile, components: &[String], json_output: bool) -> LalResult<()> {
+    debug!("Calculating update path for {:?} in {}", components, lf.name);
 
-    let result = compute(&lf, component)?;
+    let result = compute_many(lf, components)?;
 
     if json_output {
         let encoded = serde_json::to_string_pretty(&result)?;
         println!("{}", encoded);
     } else {
-        println!("Assuming {} has been updated:", component);
+        println!("Assuming {} has been updated:", components.join(", "));
         let mut i = 1;
         for stage in result.stages {
             println!("Stage {}:", i);
@@ -110,3 +150,228 @@ pub fn print(manifest: &Manifest, component: &str, json_output: bool) -> LalResu
 
     Ok(())
 }
+
+/// Outputs the update path to the current manifest for specific components
+///
+/// Given components to propagate to the current one in your working directory,
+/// work out how to propagate them through the dependency tree fully.
+/// The tree comes from `./INPUT` if present, otherwise from the published lockfiles.
+pub fn print<T: CachedBackend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    env: &str,
+    components: &[String],
+    json_output: bool,
+) -> LalResult<()> {
+    let lf = manifest_tree(manifest, backend, env)?;
+    print_for(&lf, components, json_output)
+}
+
+/// The dependency tree of a published component, from its published lockfile
+///
+/// The component is given as `name=version`.
+pub fn published_component_tree<T: CachedBackend + ?Sized>(
+    backend: &T,
+    component: &str,
+    env: &str,
+) -> LalResult<Lockfile> {
+    let pair: Vec<&str> = component.split('=').collect();
+    if pair.len() != 2 {
+        return Err(CliError::InvalidComponentVersion(component.into()));
+    }
+    let version = pair[1].parse::<Version>()?;
+    Ok(backend.retrieve_published_lockfile(pair[0], &version, env)?.set_name(pair[0]))
+}
+
+/// Progress of an executing propagation
+///
+/// This is saved in the workspace after every step so that a failed propagation
+/// can be resumed where it stopped.
+#[derive(Serialize, Deserialize)]
+pub struct PropagationState {
+    /// Components being propagated
+    pub components: Vec<String>,
+    /// Environment everything is built and published in
+    pub environment: String,
+    /// Update steps to perform
+    pub sequence: UpdateSequence,
+    /// Versions of the propagated component and of the repos being published
+    pub versions: BTreeMap<String, Version>,
+    /// Repos that have been updated, built and published
+    pub done: BTreeSet<String>,
+}
+
+impl PropagationState {
+    /// Location of the state file in a workspace
+    pub fn path(workspace: &Path) -> PathBuf { workspace.join(".lal-propagate.json") }
+
+    /// Read the state of an unfinished propagation in a workspace (if any)
+    pub fn read(workspace: &Path) -> LalResult<Option<PropagationState>> {
+        let state_path = PropagationState::path(workspace);
+        if !state_path.exists() {
+            return Ok(None);
+        }
+        let mut state_data = String::new();
+        fs::File::open(&state_path)?.read_to_string(&mut state_data)?;
+        Ok(Some(serde_json::from_str(&state_data)?))
+    }
+
+    /// Overwrite the state file in a workspace
+    pub fn write(&self, workspace: &Path) -> LalResult<()> {
+        let encoded = serde_json::to_string_pretty(self)?;
+        let mut f = fs::File::create(PropagationState::path(workspace))?;
+        write!(f, "{}\n", encoded)?;
+        Ok(())
+    }
+
+    /// Mark repos that are already published at their recorded version as done
+    ///
+    /// A propagation can stop between publishing a repo and saving its state,
+    /// so resuming checks the backend rather than publishing the same version again.
+    pub fn mark_published<T: Backend + ?Sized>(&mut self, backend: &T) {
+        let pending: Vec<(String, Version)> = self.versions
+            .iter()
+            .filter(|&(r, _)| !self.done.contains(r) && !self.components.contains(r))
+            .map(|(r, v)| (r.clone(), v.clone()))
+            .collect();
+        for (repo, version) in pending {
+            match backend.get_versions(&repo, &self.environment) {
+                Ok(ref versions) if versions.contains(&version) => {
+                    info!("{} was already published at {}", repo, version);
+                    self.done.insert(repo);
+                }
+                Ok(_) => {}
+                Err(e) => debug!("Unable to check if {} is published ({})", repo, e),
+            }
+        }
+    }
+}
+
+// the version to publish a repo at next (1 for a repo without any published versions)
+fn next_version<T: Backend + ?Sized>(backend: &T, name: &str, env: &str) -> LalResult<Version> {
+    let versions = backend.get_versions(name, env).map_err(|e| {
+        warn!("Failed to list the published versions of {} ({})", name, e);
+        CliError::UnknownNextVersion(name.into(), env.into())
+    })?;
+    Ok(match versions.into_iter().max() {
+        None => Version::Integer(1),
+        Some(Version::Integer(n)) => Version::Integer(n + 1),
+        Some(Version::Semver(mut v)) => {
+            v.increment_patch();
+            Version::Semver(v)
+        }
+    })
+}
+
+// run (or with dry_run, only print) a lal command in a repo
+fn run_lal(dir: &Path, args: &[String], dry_run: bool) -> LalResult<()> {
+    println!("  (cd {} && lal {})", dir.display(), args.join(" "));
+    if dry_run {
+        return Ok(());
+    }
+    let s = Command::new(env::current_exe()?).args(args).current_dir(dir).status()?;
+    if !s.success() {
+        return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
+    }
+    Ok(())
+}
+
+/// Perform a propagation in a workspace of checked out repos
+///
+/// Every stage of the update sequence is performed in order. For every update,
+/// the repo in `workspace/<repo>` gets the equivalent of `lal fetch`,
+/// `lal update --save` of the components handled before it, `lal build -r` with the next
+/// version, and `lal publish`. The propagated components are taken at their latest versions.
+///
+/// Progress is saved in the workspace after every repo, and a failed propagation
+/// resumes from the failing repo when run again (skipping it if it was published).
+/// With `dry_run`, the commands are printed without running anything.
+pub fn execute<T: CachedBackend + Backend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    env: &str,
+    components: &[String],
+    workspace: &Path,
+    dry_run: bool,
+) -> LalResult<()> {
+    let mut state = match PropagationState::read(workspace)? {
+        Some(ref s) if s.components != components || s.environment != env => {
+            let state_path = PropagationState::path(workspace);
+            warn!("Remove {} to start a new propagation", state_path.display());
+            let names = s.components.join(", ");
+            return Err(CliError::PropagationInProgress(names, s.environment.clone()));
+        }
+        Some(mut s) => {
+            s.mark_published(backend);
+            info!("Resuming propagation ({} repos done)", s.done.len());
+            s
+        }
+        None => {
+            let lf = manifest_tree(manifest, backend, env)?;
+            let mut versions = BTreeMap::new();
+            for c in components {
+                versions.insert(c.clone(), backend.get_latest_version(c, env)?);
+            }
+            PropagationState {
+                components: components.to_vec(),
+                environment: env.into(),
+                sequence: compute_many(&lf, components)?,
+                versions: versions,
+                done: BTreeSet::new(),
+            }
+        }
+    };
+    for c in components {
+        println!("Propagating {}={}", c, state.versions[c]);
+    }
+
+    let stages = state.sequence.stages.clone();
+    for (i, stage) in stages.into_iter().enumerate() {
+        println!("Stage {}:", i + 1);
+        for update in stage.updates {
+            let repo = update.repo;
+            if state.done.contains(&repo) {
+                println!("  {} already published at {}", repo, state.versions[&repo]);
+                continue;
+            }
+            let dir = workspace.join(&repo);
+            let mf = Manifest::read_from(&dir)?;
+            let version = match state.versions.get(&repo).cloned() {
+                Some(v) => v,
+                None => next_version(backend, &repo, env)?,
+            };
+            state.versions.insert(repo.clone(), version.clone());
+            if !dry_run {
+                state.write(workspace)?; // keep the version if we have to resume
+            }
+
+            let envarg = vec!["-e".to_string(), env.to_string()];
+            run_lal(&dir, &[envarg.clone(), vec!["fetch".into()]].concat(), dry_run)?;
+            for &(dev, flag) in &[(false, "--save"), (true, "--save-dev")] {
+                let deps: Vec<String> = update.dependencies
+                    .iter()
+                    .filter(|d| mf.devDependencies.contains_key(*d) == dev)
+                    .map(|d| format!("{}={}", d, state.versions[d]))
+                    .collect();
+                if !deps.is_empty() {
+                    let args = vec![vec!["update".into()], deps, vec![flag.into()]].concat();
+                    run_lal(&dir, &[envarg.clone(), args].concat(), dry_run)?;
+                }
+            }
+            let build = vec!["build".into(), "-r".into(), format!("--with-version={}", version)];
+            run_lal(&dir, &[envarg.clone(), build].concat(), dry_run)?;
+            run_lal(&dir, &["publish".into(), repo.clone()], dry_run)?;
+
+            state.done.insert(repo);
+            if !dry_run {
+                state.write(workspace)?;
+            }
+        }
+    }
+
+    if !dry_run {
+        fs::remove_file(PropagationState::path(workspace))?;
+        info!("Propagated {} to {}", components.join(", "), manifest.name);
+    }
+    Ok(())
+}
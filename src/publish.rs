@@ -2,7 +2,7 @@ use std::path::Path;
 
 // Need both the struct and the trait
 use storage::Backend;
-use super::{LalResult, CliError, Lockfile};
+use super::{LalResult, CliError, Lockfile, Version};
 
 /// Publish a release build to the storage backend
 ///
@@ -19,7 +19,7 @@ pub fn publish<T: Backend + ?Sized>(name: &str, backend: &T) -> LalResult<()> {
     let lock = Lockfile::release_build()?;
 
     let version = lock.version
-        .parse::<u32>()
+        .parse::<Version>()
         .map_err(|e| {
             error!("Release build not done --with-version=$BUILD_VERSION");
             debug!("Error: {}", e);
@@ -34,7 +34,7 @@ pub fn publish<T: Backend + ?Sized>(name: &str, backend: &T) -> LalResult<()> {
     let env = lock.environment;
 
     info!("Publishing {}={} to {}", name, version, env);
-    backend.publish_artifact(name, version, &env)?;
+    backend.publish_artifact(name, &version, &env)?;
 
     Ok(())
 }
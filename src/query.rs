@@ -1,10 +1,20 @@
 use std::io::{self, Write};
+use serde_json;
 
-use storage::Backend;
-use super::{LalResult, CliError};
+use storage::{Backend, CachedBackend};
+use super::{LalResult, CliError, Version};
 
 /// Prints a list of versions associated with a component
-pub fn query(backend: &Backend, _env: Option<&str>, component: &str, last: bool) -> LalResult<()> {
+///
+/// With `lockfile`, the published lockfile of the latest version (or a `component=version`)
+/// is printed instead. Only the lockfile is fetched, not the tarball.
+pub fn query(
+    backend: &Backend,
+    _env: Option<&str>,
+    component: &str,
+    last: bool,
+    lockfile: bool,
+) -> LalResult<()> {
     if component.to_lowercase() != component {
         return Err(CliError::InvalidComponentName(component.into()));
     }
@@ -16,7 +26,16 @@ pub fn query(backend: &Backend, _env: Option<&str>, component: &str, last: bool)
         Some(e) => e
     };
 
-    if last {
+    if lockfile {
+        let (name, version) = if component.contains('=') {
+            let pair: Vec<&str> = component.split('=').collect();
+            (pair[0], pair[1].parse::<Version>()?)
+        } else {
+            (component, backend.get_latest_version(component, env)?)
+        };
+        let lf = backend.retrieve_published_lockfile(name, &version, env)?;
+        println!("{}", serde_json::to_string_pretty(&lf)?);
+    } else if last {
         let ver = backend.get_latest_version(component, env)?;
         println!("{}", ver);
     } else {
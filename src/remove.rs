@@ -1,7 +1,7 @@
 use std::fs;
 use std::path::Path;
 
-use super::{CliError, LalResult, Manifest};
+use super::{CliError, LalResult, Manifest, Resolution};
 
 /// Remove specific components from `./INPUT` and the manifest.
 ///
@@ -9,7 +9,8 @@ use super::{CliError, LalResult, Manifest};
 /// is found in `./INPUT` it is deleted.
 ///
 /// If one of `save` or `savedev` was set, `manifest.json` is also updated to remove
-/// the specified components from the corresponding dictionary.
+/// the specified components from the corresponding dictionary, and any versions
+/// resolved for them are dropped from `.lal/lock.json`.
 pub fn remove(manifest: &Manifest, xs: Vec<String>, save: bool, savedev: bool) -> LalResult<()> {
     debug!("Removing dependencies {:?}", xs);
 
@@ -17,6 +18,8 @@ pub fn remove(manifest: &Manifest, xs: Vec<String>, save: bool, savedev: bool) -
     if save || savedev {
         let mut mf = manifest.clone();
         let mut hmap = if save { mf.dependencies.clone() } else { mf.devDependencies.clone() };
+        let mut resolution = Resolution::read()?;
+        let mut resolved = false;
         for component in xs.clone() {
             // We could perhaps allow people to just specify ANY dependency
             // and have a generic save flag, which we could infer from
@@ -30,6 +33,7 @@ pub fn remove(manifest: &Manifest, xs: Vec<String>, save: bool, savedev: bool) -
             }
             debug!("Removing {} from manifest", component);
             hmap.remove(&component);
+            resolved |= resolution.dependencies.remove(&component).is_some();
         }
         if save {
             mf.dependencies = hmap;
@@ -38,6 +42,9 @@ pub fn remove(manifest: &Manifest, xs: Vec<String>, save: bool, savedev: bool) -
         }
         info!("Updating manifest with removed dependencies");
         mf.write()?;
+        if resolved {
+            resolution.write()?;
+        }
     }
 
     // delete the folder (ignore if the folder does not exist)
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::path::Path;
+use std::collections::BTreeMap;
+
+use storage::CachedBackend;
+use super::{CliError, LalResult, Lockfile, Container, Version};
+
+/// A component along a chain of dependencies
+#[derive(Serialize, Clone, Debug)]
+pub struct Hop {
+    /// Name of the component
+    pub name: String,
+    /// Version it is used at
+    pub version: String,
+    /// Environment it was built in
+    pub environment: String,
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={} ({})", self.name, self.version, self.environment)
+    }
+}
+
+/// The components leading from a root component (exclusive) to a dependency (inclusive)
+pub type Chain = Vec<Hop>;
+
+/// Render a chain from its root like `root -> libfoo=3 (xenial) -> ciscossl=41 (xenial)`
+pub fn format_chain(root: &str, chain: &[Hop]) -> String {
+    let mut res = root.to_string();
+    for hop in chain {
+        res.push_str(&format!(" -> {}", hop));
+    }
+    res
+}
+
+/// Every chain of dependencies in a lockfile tree
+///
+/// There is one chain for every node below the root, so a component used in several
+/// places in the tree ends several chains.
+pub fn chains(lf: &Lockfile) -> Vec<Chain> {
+    let mut res = vec![];
+    collect_chains(lf, &mut vec![], &mut res);
+    res
+}
+
+fn collect_chains(lf: &Lockfile, prefix: &mut Chain, acc: &mut Vec<Chain>) {
+    for (name, dep) in &lf.dependencies {
+        prefix.push(Hop {
+            name: name.clone(),
+            version: dep.version.clone(),
+            environment: dep.environment.clone(),
+        });
+        acc.push(prefix.clone());
+        collect_chains(dep, prefix, acc);
+        prefix.pop();
+    }
+}
+
+/// An inconsistency in a dependency tree
+#[derive(Debug)]
+pub enum Problem {
+    /// A component installed in `./INPUT` that is used at several versions in the tree
+    Conflict(String, Vec<Chain>),
+    /// A component that ends up depending on itself
+    Cycle(Chain),
+    /// A component built in a different environment than the one used
+    EnvironmentMismatch(Chain),
+}
+
+impl Problem {
+    /// Human readable description with the full paths from the root
+    pub fn describe(&self, root: &str) -> String {
+        match *self {
+            Problem::Conflict(ref name, ref chains) => {
+                let mut res = format!("{} is used at multiple versions:", name);
+                for chain in chains {
+                    res.push_str(&format!("\n  {}", format_chain(root, chain)));
+                }
+                res
+            }
+            Problem::Cycle(ref chain) => {
+                format!("Dependency cycle: {}", format_chain(root, chain))
+            }
+            Problem::EnvironmentMismatch(ref chain) => {
+                format!("Environment mismatch: {}", format_chain(root, chain))
+            }
+        }
+    }
+
+    fn into_error(self) -> CliError {
+        match self {
+            Problem::Conflict(name, _) => CliError::MultipleVersions(name),
+            Problem::Cycle(chain) => CliError::DependencyCycle(chain[chain.len() - 1].name.clone()),
+            Problem::EnvironmentMismatch(chain) => {
+                let hop = chain[chain.len() - 1].clone();
+                CliError::EnvironmentMismatch(hop.name, hop.environment)
+            }
+        }
+    }
+}
+
+// whether the last component of a chain already appeared before it
+fn repeats(root: &str, chain: &[Hop]) -> bool {
+    match chain.split_last() {
+        Some((last, rest)) => last.name == root || rest.iter().any(|h| h.name == last.name),
+        None => false,
+    }
+}
+
+/// Find all inconsistencies in a dependency tree
+///
+/// The direct dependencies of the root are what gets installed flat into `./INPUT`,
+/// so these must be used at a single version throughout the tree.
+/// Every component must also have been built in `env`, and nothing can depend on itself.
+pub fn problems(lf: &Lockfile, env: &str) -> Vec<Problem> {
+    let mut res = vec![];
+    let mut uses: BTreeMap<String, BTreeMap<String, Vec<Chain>>> = BTreeMap::new();
+    for chain in chains(lf) {
+        // only report the shortest chain through any one cycle
+        if repeats(&lf.name, &chain) && !(1..chain.len()).any(|i| repeats(&lf.name, &chain[..i])) {
+            res.push(Problem::Cycle(chain.clone()));
+        }
+        let hop = chain[chain.len() - 1].clone();
+        if hop.environment != env {
+            res.push(Problem::EnvironmentMismatch(chain.clone()));
+        }
+        uses.entry(hop.name)
+            .or_insert_with(BTreeMap::new)
+            .entry(hop.version)
+            .or_insert_with(Vec::new)
+            .push(chain);
+    }
+    for (name, versions) in uses {
+        if versions.len() > 1 && lf.dependencies.contains_key(&name) {
+            let chains = versions.into_iter().flat_map(|(_, cs)| cs).collect();
+            res.push(Problem::Conflict(name, chains));
+        }
+    }
+    res
+}
+
+/// Check a dependency tree for version conflicts, cycles and environment mismatches
+///
+/// Every problem is logged along with the paths leading to it, and the first is returned.
+pub fn check(lf: &Lockfile, env: &str) -> LalResult<()> {
+    let mut found = problems(lf, env);
+    for p in &found {
+        warn!("{}", p.describe(&lf.name));
+    }
+    if found.is_empty() { Ok(()) } else { Err(found.remove(0).into_error()) }
+}
+
+// lockfile of a published component, preferring an identical one in INPUT
+fn lockfile_for<T: CachedBackend + ?Sized>(
+    backend: &T,
+    name: &str,
+    version: &Version,
+    env: &str,
+) -> LalResult<Lockfile> {
+    let lock_path = Path::new("./INPUT").join(name).join("lockfile.json");
+    if lock_path.is_file() {
+        let lf = Lockfile::from_path(&lock_path, name)?;
+        if lf.version == version.to_string() && lf.environment == env {
+            return Ok(lf);
+        }
+    }
+    backend.retrieve_published_lockfile(name, version, env)
+}
+
+/// Dependency tree of a set of published components without downloading them
+///
+/// Components already in `./INPUT` at the same version and environment use the lockfile
+/// there, and only the published (and cached) lockfiles of the rest are fetched.
+/// Components without a retrievable lockfile are treated as having no dependencies.
+pub fn published_tree<T: CachedBackend + ?Sized>(
+    backend: &T,
+    root: &str,
+    deps: &BTreeMap<String, Version>,
+    env: &str,
+) -> Lockfile {
+    let mut lf = Lockfile::default().set_name(root);
+    lf.environment = env.into();
+    for (name, version) in deps {
+        let dep = lockfile_for(backend, name, version, env).unwrap_or_else(|e| {
+            warn!("Unable to check the dependencies of {}={} ({})", name, version, e);
+            Lockfile::new(name,
+                          &Container::default(),
+                          env,
+                          Some(version.to_string()),
+                          None)
+        });
+        lf.dependencies.insert(name.clone(), dep);
+    }
+    lf
+}
+
+/// Check the full tree of a set of published components before installing them
+pub fn check_published<T: CachedBackend + ?Sized>(
+    backend: &T,
+    root: &str,
+    deps: &BTreeMap<String, Version>,
+    env: &str,
+) -> LalResult<()> {
+    debug!("Resolving the dependency tree of {:?}", deps);
+    let tree = published_tree(backend, root, deps, env);
+    check(&tree, env)
+}
@@ -3,7 +3,7 @@ use std::env;
 use std::path::Path;
 use std::vec::Vec;
 
-use super::{Config, Container, CliError, LalResult};
+use super::{Config, Container, ContainerRuntime, CliError, LalResult};
 
 /// Verifies that `id -u` and `id -g` are both 1000
 ///
@@ -30,15 +30,18 @@ fn permission_sanity_check() -> LalResult<()> {
 
 /// Gets the ID of a docker container
 ///
-/// Uses the `docker images` command to find the image ID of the specified
-/// container.
+/// Uses the `docker images` command (or `podman images`) to find the image ID of the
+/// specified container.
 /// Will return a trimmed String containing the image ID requested, wrapped in
 /// a Result::Ok, or CliError::DockerImageNotFound wrapped in a Result::Err if
 /// docker images returns no output.
-fn get_docker_image_id(container: &Container) -> LalResult<String> {
-    trace!("Using docker images to find ID of container {}", container);
-    let image_id_output =
-        Command::new("docker").arg("images").arg("-q").arg(container.to_string()).output()?;
+fn get_docker_image_id(runtime: ContainerRuntime, container: &Container) -> LalResult<String> {
+    trace!("Using {} images to find ID of container {}", runtime.binary(), container);
+    let image_id_output = Command::new(runtime.binary())
+        .arg("images")
+        .arg("-q")
+        .arg(container.to_string())
+        .output()?;
     let image_id_str: String = String::from_utf8_lossy(&image_id_output.stdout).trim().into();
     match image_id_str.len() {
         0 => {
@@ -54,13 +57,14 @@ fn get_docker_image_id(container: &Container) -> LalResult<String> {
 
 /// Pulls a docker container
 ///
-/// Uses `docker pull` to pull the specified container from the docker repository.
+/// Uses `docker pull` (or `podman pull`) to pull the specified container from the
+/// docker repository.
 /// Returns Ok(()) if the command is successful, Err(CliError::SubprocessFailure)
 /// if `docker pull` fails or is interrupted by a signal, Err(CliError::Io) if the
 /// command status() call fails for a different reason.
-fn pull_docker_image(container: &Container) -> LalResult<()> {
+fn pull_docker_image(runtime: ContainerRuntime, container: &Container) -> LalResult<()> {
     trace!("Pulling container {}", container);
-    let s = Command::new("docker").arg("pull").arg(container.to_string()).status()?;
+    let s = Command::new(runtime.binary()).arg("pull").arg(container.to_string()).status()?;
     if !s.success() {
         trace!("Pull failed");
         return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
@@ -69,6 +73,54 @@ fn pull_docker_image(container: &Container) -> LalResult<()> {
     Ok(())
 }
 
+// digest of a local image from its repo digests (if it came from a registry)
+fn get_docker_image_digest(runtime: ContainerRuntime, container: &Container) -> Option<String> {
+    trace!("Using {} image inspect to find digest of {}", runtime.binary(), container);
+    let output = match Command::new(runtime.binary())
+        .args(&["image", "inspect", "--format", "{{range .RepoDigests}}{{println .}}{{end}}"])
+        .arg(container.to_string())
+        .output() {
+        Ok(o) => o,
+        Err(_) => return None,
+    };
+    if !output.status.success() {
+        return None;
+    }
+    let digests = String::from_utf8_lossy(&output.stdout);
+    let repo_digests: Vec<&str> =
+        digests.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).collect();
+    // prefer the digest for the same repository, when the image is in several
+    let prefix = format!("{}@", container.name);
+    repo_digests.iter()
+        .find(|d| d.starts_with(&prefix))
+        .or(repo_digests.first())
+        .and_then(|d| d.splitn(2, '@').nth(1))
+        .map(String::from)
+}
+
+/// Pins a container to the digest of the image its tag currently points at
+///
+/// The image is pulled if it is not available locally.
+/// Containers that are already pinned are returned as is, and images without a
+/// digest (e.g. built locally) are returned unpinned.
+pub fn resolve_digest(runtime: ContainerRuntime, container: &Container) -> LalResult<Container> {
+    if container.digest.is_some() {
+        return Ok(container.clone());
+    }
+    let digest = match get_docker_image_digest(runtime, container) {
+        Some(d) => Some(d),
+        None => {
+            pull_docker_image(runtime, container)?;
+            get_docker_image_digest(runtime, container)
+        }
+    };
+    match digest {
+        Some(ref d) => debug!("Resolved {} to {}", container, d),
+        None => warn!("No digest found for {} - not recording what it pointed at", container),
+    }
+    Ok(container.clone().with_digest(digest))
+}
+
 /// Builds a docker container
 ///
 /// Uses `docker build` to build a docker container with the specified
@@ -76,7 +128,11 @@ fn pull_docker_image(container: &Container) -> LalResult<()> {
 /// Returns Ok(()) if the command is successful, Err(CliError::SubprocessFailure)
 /// if `bash -c` fails or is interrupted by a signal, Err(CliError::Io) if the
 /// command status() call fails for a different reason.
-fn build_docker_image(container: &Container, instructions: Vec<String>) -> LalResult<()> {
+fn build_docker_image(
+    runtime: ContainerRuntime,
+    container: &Container,
+    instructions: Vec<String>,
+) -> LalResult<()> {
     trace!("Building docker image for {}", container);
     let instruction_strings = instructions.join("\\n");
     trace!("Build instructions: \n{}", instruction_strings);
@@ -84,8 +140,9 @@ fn build_docker_image(container: &Container, instructions: Vec<String>) -> LalRe
     let instruction_strings = instruction_strings.replace("'", "'\\''");
     let s = Command::new("bash")
         .arg("-c")
-        .arg(format!("echo -e '{}' | docker build --tag {} -",
+        .arg(format!("echo -e '{}' | {} build --tag {} -",
                      instruction_strings,
+                     runtime.binary(),
                      container))
         .status()?;
     if !s.success() {
@@ -115,27 +172,33 @@ pub struct DockerRunFlags {
 /// Returns a container derived from the one passed as an argument, with the `lal`
 /// user having its uid and gid modified to match the ones passed.
 /// The container is built if necessary (e.g. new base container from upstream)
-fn fixup_docker_container(container: &Container, u: u32, g: u32) -> LalResult<Container> {
+fn fixup_docker_container(
+    runtime: ContainerRuntime,
+    container: &Container,
+    u: u32,
+    g: u32,
+) -> LalResult<Container> {
     info!("Using appropriate container for user {}:{}", u, g);
     // Find image id of regular docker container
     // We might have to pull it
-    let image_id = get_docker_image_id(container)
+    let image_id = get_docker_image_id(runtime, container)
         .or_else(|_| {
-            pull_docker_image(container)?;
-            get_docker_image_id(container)
+            pull_docker_image(runtime, container)?;
+            get_docker_image_id(runtime, container)
         })?;
 
     // Produce name and tag of modified container
     let modified_container = Container {
         name: format!("{}-u{}_g{}", container.name, u, g),
         tag: format!("from_{}", image_id),
+        digest: None,
     };
 
     info!("Using container {}", modified_container);
 
     // Try to find image id of modified container
     // If we fail we need to build it
-    match get_docker_image_id(&modified_container) {
+    match get_docker_image_id(runtime, &modified_container) {
         Ok(id) => {
             info!("Found container {}, image id is {}", modified_container, id);
         }
@@ -148,17 +211,74 @@ fn fixup_docker_container(container: &Container, u: u32, g: u32) -> LalResult<Co
                     "USER lal".into(),
                 ];
             info!("Attempting to build container {}...", modified_container);
-            build_docker_image(&modified_container, instructions)?;
+            build_docker_image(runtime, &modified_container, instructions)?;
         }
     };
     trace!("Fixup for user {}:{} succeeded", u, g);
     Ok(modified_container)
 }
 
+// print a command with its arguments
+fn print_command(exe: &str, args: &[String]) {
+    print!("{}", exe);
+    for arg in args {
+        if arg.contains(' ') {
+            // leave quoted args quoted
+            print!(" \"{}\"", arg);
+        } else {
+            print!(" {}", arg);
+        }
+    }
+    println!("");
+}
+
+/// Runs an arbitrary command directly on the host instead of in a container
+///
+/// This keeps the contract of `docker_run`: the command runs in the current directory
+/// with the `KEY=VALUE` variables from `modes.env_vars` set, and no command gives
+/// an interactive bash shell. Mounts, X11 forwarding and networking modes are ignored.
+fn host_run(command: Vec<String>, modes: &ShellModes) -> LalResult<()> {
+    let mut command = command;
+    if command.is_empty() {
+        command.push("/bin/bash".into());
+    }
+    // variables without a value are passed through from the host anyway
+    let vars: Vec<(&str, &str)> = modes.env_vars
+        .iter()
+        .filter_map(|v| {
+            let mut kv = v.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(val)) => Some((k, val)),
+                _ => None,
+            }
+        })
+        .collect();
+
+    if modes.printonly {
+        let mut args: Vec<String> = vars.iter().map(|&(k, v)| format!("{}={}", k, v)).collect();
+        args.extend(command);
+        print_command("env", &args);
+    } else {
+        trace!("Running {:?} on the host", command);
+        let mut cmd = Command::new(&command[0]);
+        cmd.args(&command[1..]);
+        for &(k, v) in &vars {
+            cmd.env(k, v);
+        }
+        let s = cmd.status()?;
+        if !s.success() {
+            return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
+        }
+    }
+    Ok(())
+}
+
 /// Runs an arbitrary command in the configured docker environment
 ///
 /// This will mount the current directory as `~/volume` as well as a few conveniences,
 /// and absorb the `Stdio` supplied by this `Command`.
+/// The container is run with the `runtime` from the `Config`.
+/// With `modes.native`, the command is instead run directly on the host.
 ///
 /// This is the most general function, used by both `lal build` and `lal shell`.
 pub fn docker_run(
@@ -168,7 +288,11 @@ pub fn docker_run(
     flags: &DockerRunFlags,
     modes: &ShellModes,
 ) -> LalResult<()> {
+    if modes.native {
+        return host_run(command, modes);
+    }
 
+    let runtime = cfg.runtime;
     let mut modified_container_option: Option<Container> = None;
 
     trace!("Performing docker permission sanity check");
@@ -182,7 +306,10 @@ pub fn docker_run(
                                                                 u,
                                                                 g));
                 }
-                modified_container_option = Some(fixup_docker_container(container, u, g)?);
+                if !runtime.keeps_user_id() {
+                    modified_container_option =
+                        Some(fixup_docker_container(runtime, container, u, g)?);
+                }
             }
             x => {
                 return Err(x);
@@ -235,6 +362,11 @@ pub fn docker_run(
     if flags.privileged {
         args.push("--privileged".into())
     }
+    if runtime.keeps_user_id() {
+        // map our user onto the 1000:1000 `lal` user rather than deriving an image
+        // (also needed for 1000:1000 as rootless containers otherwise run us as root)
+        args.push("--userns=keep-id:uid=1000,gid=1000".into());
+    }
 
     args.push("-w".into());
     args.push("/home/lal/volume".into());
@@ -249,27 +381,18 @@ pub fn docker_run(
     }
     args.push((if flags.interactive { "-it" } else { "-t" }).into());
 
-    args.push(format!("{}:{}", container.name, container.tag));
+    args.push(container.to_string());
     for c in command {
         args.push(c);
     }
 
     // run or print docker command
     if modes.printonly {
-        print!("docker");
-        for arg in args {
-            if arg.contains(' ') {
-                // leave quoted args quoted
-                print!(" \"{}\"", arg);
-            } else {
-                print!(" {}", arg);
-            }
-        }
-        println!("");
+        print_command(runtime.binary(), &args);
     } else {
-        trace!("Entering docker");
-        let s = Command::new("docker").args(&args).status()?;
-        trace!("Exited docker");
+        trace!("Entering {}", runtime.binary());
+        let s = Command::new(runtime.binary()).args(&args).status()?;
+        trace!("Exited {}", runtime.binary());
         if !s.success() {
             return Err(CliError::SubprocessFailure(s.code().unwrap_or(1001)));
         }
@@ -288,6 +411,8 @@ pub struct ShellModes {
     pub host_networking: bool,
     /// Environment variables
     pub env_vars: Vec<String>,
+    /// Run directly on the host rather than in a container
+    pub native: bool,
 }
 
 
@@ -303,7 +428,9 @@ pub fn shell(
     cmd: Option<Vec<&str>>,
     privileged: bool,
 ) -> LalResult<()> {
-    if !modes.printonly {
+    if modes.native {
+        info!("Running natively on the host");
+    } else if !modes.printonly {
         info!("Entering {}", container);
     }
 
@@ -1,7 +1,7 @@
 use std::path::Path;
 
 use storage::CachedBackend;
-use super::{CliError, LalResult, Manifest, Lockfile};
+use super::{CliError, LalResult, Manifest, Lockfile, Version};
 
 
 /// Saves current build `./OUTPUT` to the local cache under a specific name
@@ -12,9 +12,9 @@ use super::{CliError, LalResult, Manifest, Lockfile};
 /// This file can then be installed via `update` using a component=${name} argument.
 pub fn stash<T: CachedBackend + ?Sized>(backend: &T, mf: &Manifest, name: &str) -> LalResult<()> {
     info!("Stashing OUTPUT into cache under {}/{}", mf.name, name);
-    // sanity: verify name does NOT parse as a u32
-    if let Ok(n) = name.parse::<u32>() {
-        return Err(CliError::InvalidStashName(n));
+    // sanity: verify name does NOT parse as a version
+    if name.parse::<Version>().is_ok() {
+        return Err(CliError::InvalidStashName(name.into()));
     }
 
     let outputdir = Path::new("./OUTPUT");
@@ -1,22 +1,21 @@
 #![allow(missing_docs)]
 
 use std::vec::Vec;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::fs::File;
 use std::path::{Path, PathBuf};
 
 #[cfg(feature = "upgrade")]
-use semver::Version;
+use semver;
 
 use serde_json;
 use sha1;
-use hyper::{self, Client};
-use hyper::net::HttpsConnector;
+use hyper::Client;
 use hyper::header::{Authorization, Basic};
 use hyper::status::StatusCode;
-use hyper_native_tls::NativeTlsClient;
 
-use core::{CliError, LalResult};
+use core::{CliError, LalResult, Lockfile, Version};
+use super::network::NetworkOptions;
 
 
 /// Artifactory credentials
@@ -41,6 +40,9 @@ pub struct ArtifactoryConfig {
     pub vgroup: String,
     /// Optional publish credentials
     pub credentials: Option<Credentials>,
+    /// Timeouts and retries for downloads
+    #[serde(default)]
+    pub network: NetworkOptions,
 }
 
 
@@ -56,65 +58,21 @@ struct ArtifactoryStorageResponse {
     children: Vec<ArtifactoryVersion>,
 }
 
-// simple request body fetcher
-fn hyper_req(url: &str) -> LalResult<String> {
-    let client = Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap()));
-    let mut res = client.get(url).send()?;
-    if res.status != hyper::Ok {
-        return Err(CliError::BackendFailure(format!("GET request with {}", res.status)));
-    }
-    let mut body = String::new();
-    res.read_to_string(&mut body)?;
-    Ok(body)
-}
-
-// simple request downloader
+// simple request downloader (with default timeouts and retries)
+#[cfg(feature = "upgrade")]
 pub fn http_download_to_path(url: &str, save: &PathBuf) -> LalResult<()> {
-    debug!("GET {}", url);
-    let client = Client::with_connector(HttpsConnector::new(NativeTlsClient::new().unwrap()));
-    let mut res = client.get(url).send()?;
-    if res.status != hyper::Ok {
-        return Err(CliError::BackendFailure(format!("GET request with {}", res.status)));
-    }
-
-    if cfg!(feature = "progress") {
-        #[cfg(feature = "progress")]
-        {
-            use indicatif::{ProgressBar, ProgressStyle};
-            let total_size = res.headers.get::<hyper::header::ContentLength>().unwrap().0;
-            let mut downloaded = 0;
-            let mut buffer = [0; 1024 * 64];
-            let mut f = File::create(save)?;
-            let pb = ProgressBar::new(total_size);
-            pb.set_style(ProgressStyle::default_bar()
-                             .template("{bar:40.yellow/black} {bytes}/{total_bytes} ({eta})"));
-
-            while downloaded < total_size {
-                let read = res.read(&mut buffer)?;
-                f.write_all(&buffer[0..read])?;
-                downloaded += read as u64;
-                pb.set_position(downloaded);
-            }
-            f.flush()?;
-        }
-    } else {
-        let mut buffer: Vec<u8> = Vec::new();
-        res.read_to_end(&mut buffer)?;
-        let mut f = File::create(save)?;
-        f.write_all(&buffer)?;
-    }
-    Ok(())
+    NetworkOptions::default().download(url, save)
 }
 
 
 /// Query the Artifactory storage api
 ///
-/// This will get, then parse all results as u32s, and return this list.
-/// This assumes versoning is done via a single integer.
-fn get_storage_versions(uri: &str) -> LalResult<Vec<u32>> {
+/// This will get, then parse all results as versions, and return this list.
+/// Folders that are not valid versions are ignored.
+fn get_storage_versions(net: &NetworkOptions, uri: &str) -> LalResult<Vec<Version>> {
     debug!("GET {}", uri);
 
-    let resp = hyper_req(uri)
+    let resp = net.get_string(uri)
         .map_err(|e| {
             warn!("Failed to GET {}: {}", uri, e);
             CliError::BackendFailure("No version information found on API".into())
@@ -123,13 +81,13 @@ fn get_storage_versions(uri: &str) -> LalResult<Vec<u32>> {
     trace!("Got body {}", resp);
 
     let res: ArtifactoryStorageResponse = serde_json::from_str(&resp)?;
-    let mut builds: Vec<u32> = res.children
+    let mut builds: Vec<Version> = res.children
         .iter()
         .map(|r| r.uri.as_str())
         .map(|r| r.trim_matches('/'))
         .filter_map(|b| b.parse().ok())
         .collect();
-    builds.sort_by(|a, b| b.cmp(a)); // sort by version number descending
+    builds.sort_by(|a, b| b.cmp(a)); // sort by version descending
     Ok(builds)
 }
 
@@ -192,9 +150,9 @@ fn upload_artifact(arti: &ArtifactoryConfig, uri: &str, f: &mut File) -> LalResu
     }
 }
 
-/// Get the maximal version number from the storage api
-fn get_storage_as_u32(uri: &str) -> LalResult<u32> {
-    if let Some(&latest) = get_storage_versions(uri)?.iter().max() {
+/// Get the maximal version from the storage api
+fn get_storage_latest(net: &NetworkOptions, uri: &str) -> LalResult<Version> {
+    if let Some(latest) = get_storage_versions(net, uri)?.into_iter().max() {
         Ok(latest)
     } else {
         Err(CliError::BackendFailure("No version information found on API".into()))
@@ -205,7 +163,7 @@ fn get_storage_as_u32(uri: &str) -> LalResult<u32> {
 fn get_dependency_env_url(
     art_cfg: &ArtifactoryConfig,
     name: &str,
-    version: u32,
+    version: &Version,
     env: &str,
 ) -> String {
     let tar_url = format!("{}/{}/env/{}/{}/{}/{}.tar.gz",
@@ -213,7 +171,7 @@ fn get_dependency_env_url(
                           art_cfg.vgroup,
                           env,
                           name,
-                          version.to_string(),
+                          version,
                           name);
 
     trace!("Inferring tarball location as {}", tar_url);
@@ -231,11 +189,11 @@ fn get_dependency_url_latest(
                       "env",
                       env,
                       name);
-    let v = get_storage_as_u32(&url)?;
+    let v = get_storage_latest(&art_cfg.network, &url)?;
 
     debug!("Found latest version as {}", v);
     Ok(Component {
-           location: get_dependency_env_url(art_cfg, name, v, env),
+           location: get_dependency_env_url(art_cfg, name, &v, env),
            version: v,
            name: name.into(),
        })
@@ -243,7 +201,11 @@ fn get_dependency_url_latest(
 
 // This queries the API for the default location
 // if a default exists, then all our current multi-builds must exist
-fn get_latest_versions(art_cfg: &ArtifactoryConfig, name: &str, env: &str) -> LalResult<Vec<u32>> {
+fn get_latest_versions(
+    art_cfg: &ArtifactoryConfig,
+    name: &str,
+    env: &str,
+) -> LalResult<Vec<Version>> {
     let url = format!("{}/api/storage/{}/{}/{}/{}",
                       art_cfg.master,
                       art_cfg.release,
@@ -251,19 +213,19 @@ fn get_latest_versions(art_cfg: &ArtifactoryConfig, name: &str, env: &str) -> La
                       env,
                       name);
 
-    get_storage_versions(&url)
+    get_storage_versions(&art_cfg.network, &url)
 }
 
 /// Main entry point for install
 fn get_tarball_uri(
     art_cfg: &ArtifactoryConfig,
     name: &str,
-    version: Option<u32>,
+    version: Option<Version>,
     env: &str,
 ) -> LalResult<Component> {
     if let Some(v) = version {
         Ok(Component {
-               location: get_dependency_env_url(art_cfg, name, v, env),
+               location: get_dependency_env_url(art_cfg, name, &v, env),
                version: v,
                name: name.into(),
            })
@@ -278,13 +240,13 @@ pub struct LatestLal {
     /// URL of the latest tarball
     pub url: String,
     /// Semver::Version of the latest tarball
-    pub version: Version,
+    pub version: semver::Version,
 }
 
 /// Entry point for `lal::upgrade`
 ///
-/// This mostly duplicates the behaviour in `get_storage_as_u32`, however,
-/// it is parsing the version as a `semver::Version` struct rather than a u32.
+/// This mostly duplicates the behaviour in `get_storage_latest`, however,
+/// it only considers semver versions, as lal itself is versioned that way.
 /// This is used regardless of your used backend because we want people to use our
 /// main release of lal on CME-release on cisco artifactory at the moment.
 #[cfg(feature = "upgrade")]
@@ -292,7 +254,8 @@ pub fn get_latest_lal_version() -> LalResult<LatestLal> {
     // canonical latest url
     let uri = "https://engci-maven-master.cisco.com/artifactory/api/storage/CME-release/lal";
     debug!("GET {}", uri);
-    let resp = hyper_req(uri)
+    let resp = NetworkOptions::default()
+        .get_string(uri)
         .map_err(|e| {
             warn!("Failed to GET {}: {}", uri, e);
             CliError::BackendFailure("No version information found on API".into())
@@ -300,11 +263,11 @@ pub fn get_latest_lal_version() -> LalResult<LatestLal> {
     trace!("Got body {}", resp);
 
     let res: ArtifactoryStorageResponse = serde_json::from_str(&resp)?;
-    let latest: Option<Version> = res.children
+    let latest: Option<semver::Version> = res.children
         .iter()
         .map(|r| r.uri.trim_matches('/').to_string())
         .inspect(|v| trace!("Found lal version {}", v))
-        .filter_map(|v| Version::parse(&v).ok())
+        .filter_map(|v| semver::Version::parse(&v).ok())
         .max(); // Semver::Version implements an order
 
     if let Some(l) = latest {
@@ -344,11 +307,11 @@ impl ArtifactoryBackend {
 /// This is intended to be used by the caching trait `CachedBackend`, but for
 /// specific low-level use cases, these methods can be used directly.
 impl Backend for ArtifactoryBackend {
-    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<u32>> {
+    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<Version>> {
         get_latest_versions(&self.config, name, loc)
     }
 
-    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<u32> {
+    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<Version> {
         let latest = get_dependency_url_latest(&self.config, name, loc)?;
         Ok(latest.version)
     }
@@ -356,13 +319,13 @@ impl Backend for ArtifactoryBackend {
     fn get_component_info(
         &self,
         name: &str,
-        version: Option<u32>,
+        version: Option<Version>,
         loc: &str,
     ) -> LalResult<Component> {
         get_tarball_uri(&self.config, name, version, loc)
     }
 
-    fn publish_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()> {
+    fn publish_artifact(&self, name: &str, version: &Version, env: &str) -> LalResult<()> {
         // this fn basically assumes all the sanity checks have been performed
         // files must exist and lockfile must be sensible
         let artdir = Path::new("./ARTIFACT");
@@ -385,6 +348,22 @@ impl Backend for ArtifactoryBackend {
     fn get_cache_dir(&self) -> String { self.cache.clone() }
 
     fn raw_fetch(&self, url: &str, dest: &PathBuf) -> LalResult<()> {
-        http_download_to_path(url, dest)
+        self.config.network.download(url, dest)
+    }
+
+    fn get_component_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile> {
+        let url = format!("{}/{}/env/{}/{}/{}/lockfile.json",
+                          self.config.slave,
+                          self.config.vgroup,
+                          env,
+                          name,
+                          version);
+        trace!("Fetching lockfile from {}", url);
+        Ok(serde_json::from_str(&self.config.network.get_string(&url)?)?)
     }
 }
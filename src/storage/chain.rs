@@ -0,0 +1,161 @@
+#![allow(missing_docs)]
+
+use std::vec::Vec;
+use std::path::PathBuf;
+
+use core::{CliError, LalResult, Lockfile, Version};
+use super::{Backend, BackendConfiguration, Component};
+
+
+/// Ordered list of backends to read from, with one primary to publish to
+///
+/// Typically a team-local mirror in front of a slower or less reliable main store.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ChainConfig {
+    /// Backends to try in order for reads
+    pub backends: Vec<BackendConfiguration>,
+    /// Index into `backends` of the backend that receives publishes
+    pub primary: usize,
+}
+
+/// A layered backend that falls back through several backends
+pub struct ChainBackend {
+    /// Backends in order of preference
+    pub backends: Vec<Box<Backend>>,
+    /// Index of the backend to publish to
+    pub primary: usize,
+    /// Cache directory
+    pub cache: String,
+}
+
+impl ChainBackend {
+    pub fn new(cfg: &ChainConfig, cache: &str) -> Self {
+        ChainBackend {
+            backends: cfg.backends.iter().map(|b| b.to_backend(cache)).collect(),
+            primary: cfg.primary,
+            cache: cache.into(),
+        }
+    }
+}
+
+// Separator for the per-backend locations encoded in `Component::location`
+const LOCATION_SEPARATOR: &'static str = "\n";
+
+/// Artifact backend trait for `ChainBackend`
+///
+/// Reads try every backend in turn so an unreachable backend is masked by the others.
+/// Locations handed out by `get_component_info` contain one location per backend
+/// so that `raw_fetch` can fall back in the same order.
+impl Backend for ChainBackend {
+    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<Version>> {
+        let mut versions = vec![];
+        let mut err = None;
+        let mut found = false;
+        for (i, backend) in self.backends.iter().enumerate() {
+            match backend.get_versions(name, loc) {
+                Ok(vs) => {
+                    found = true;
+                    versions.extend(vs);
+                }
+                Err(e) => {
+                    debug!("Backend {} failed to list versions of {}: {}", i, name, e);
+                    err = Some(e);
+                }
+            }
+        }
+        if !found {
+            return Err(err.unwrap_or(CliError::BackendFailure("No backends configured".into())));
+        }
+        versions.sort_by(|a, b| b.cmp(a)); // sort by version number descending
+        versions.dedup();
+        Ok(versions)
+    }
+
+    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<Version> {
+        if let Some(latest) = self.get_versions(name, loc)?.into_iter().max() {
+            return Ok(latest);
+        }
+        Err(CliError::BackendFailure("No version information found in any backend".into()))
+    }
+
+    fn get_component_info(
+        &self,
+        name: &str,
+        version: Option<Version>,
+        loc: &str,
+    ) -> LalResult<Component> {
+        let v = if let Some(ver) = version {
+            ver
+        } else {
+            self.get_latest_version(name, loc)?
+        };
+        let mut err = None;
+        let mut locations = vec![];
+        for (i, backend) in self.backends.iter().enumerate() {
+            match backend.get_component_info(name, Some(v.clone()), loc) {
+                Ok(c) => locations.push(c.location),
+                Err(e) => {
+                    debug!("Backend {} has no info on {}={}: {}", i, name, v, e);
+                    locations.push(String::new());
+                    err = Some(e);
+                }
+            }
+        }
+        if locations.iter().all(|l| l.is_empty()) {
+            return Err(err.unwrap_or(CliError::BackendFailure("No backends configured".into())));
+        }
+        Ok(Component {
+            name: name.into(),
+            version: v,
+            location: locations.join(LOCATION_SEPARATOR),
+        })
+    }
+
+    fn publish_artifact(&self, name: &str, version: &Version, env: &str) -> LalResult<()> {
+        match self.backends.get(self.primary) {
+            Some(backend) => backend.publish_artifact(name, version, env),
+            None => {
+                let reason = format!("Primary backend {} not found in chain", self.primary);
+                Err(CliError::BackendFailure(reason))
+            }
+        }
+    }
+
+    fn get_cache_dir(&self) -> String { self.cache.clone() }
+
+    fn raw_fetch(&self, location: &str, dest: &PathBuf) -> LalResult<()> {
+        let mut err = None;
+        for (backend, loc) in self.backends.iter().zip(location.split(LOCATION_SEPARATOR)) {
+            if loc.is_empty() {
+                continue;
+            }
+            match backend.raw_fetch(loc, dest) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    warn!("Failed to fetch {} - trying next backend ({})", loc, e);
+                    err = Some(e);
+                }
+            }
+        }
+        Err(err.unwrap_or(CliError::BackendFailure(format!("No location for {}", location))))
+    }
+
+    fn get_component_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile> {
+        let mut err = None;
+        for (i, backend) in self.backends.iter().enumerate() {
+            match backend.get_component_lockfile(name, version, env) {
+                Ok(lf) => return Ok(lf),
+                Err(e) => {
+                    debug!("Backend {} has no lockfile for {}={}: {}", i, name, version, e);
+                    err = Some(e);
+                }
+            }
+        }
+        Err(err.unwrap_or(CliError::BackendFailure("No backends configured".into())))
+    }
+}
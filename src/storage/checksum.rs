@@ -0,0 +1,173 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use hyper::header::Headers;
+use openssl::error::ErrorStack;
+use openssl::hash::{Hasher, MessageDigest};
+
+use core::{CliError, LalResult};
+
+/// Hash algorithms used for artifact checksums
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashType {
+    /// SHA1 - what Artifactory deploys with
+    Sha1,
+    /// SHA256 - what lal records itself
+    Sha256,
+}
+
+impl HashType {
+    /// File extension used for sidecar files of this type
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            HashType::Sha1 => "sha1",
+            HashType::Sha256 => "sha256",
+        }
+    }
+
+    fn digest(&self) -> MessageDigest {
+        match *self {
+            HashType::Sha1 => MessageDigest::sha1(),
+            HashType::Sha256 => MessageDigest::sha256(),
+        }
+    }
+
+    fn hex_len(&self) -> usize {
+        match *self {
+            HashType::Sha1 => 40,
+            HashType::Sha256 => 64,
+        }
+    }
+}
+
+/// Lowercase hex encoding of a digest
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join("")
+}
+
+/// A checksum of a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checksum {
+    /// Algorithm used
+    pub kind: HashType,
+    /// Lowercase hex digest
+    pub hex: String,
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.kind.extension(), self.hex)
+    }
+}
+
+// Path of a sidecar for a file, e.g. `foo.tar.gz.sha256`
+fn sidecar_path(pth: &Path, kind: HashType) -> PathBuf {
+    let mut name = pth.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".");
+    name.push(kind.extension());
+    pth.with_file_name(name)
+}
+
+impl Checksum {
+    /// Parse a checksum from a header value or a `sha256sum` style sidecar
+    ///
+    /// Only the first word is considered, so `<hex>  <filename>` lines are accepted.
+    pub fn parse(kind: HashType, s: &str) -> Option<Checksum> {
+        let hex = s.split_whitespace().next().unwrap_or("").to_lowercase();
+        if hex.len() != kind.hex_len() || !hex.chars().all(|c| c.is_digit(16)) {
+            return None;
+        }
+        Some(Checksum { kind: kind, hex: hex })
+    }
+
+    /// Hash a file on disk
+    pub fn of_file(kind: HashType, pth: &Path) -> LalResult<Checksum> {
+        let herr = |e: ErrorStack| CliError::BackendFailure(format!("hashing failed: {}", e));
+        let mut hasher = Hasher::new(kind.digest()).map_err(&herr)?;
+        let mut f = File::open(pth)?;
+        let mut buffer = [0; 1024 * 64];
+        loop {
+            let read = f.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[0..read]).map_err(&herr)?;
+        }
+        let digest = hasher.finish().map_err(&herr)?;
+        Ok(Checksum {
+            kind: kind,
+            hex: to_hex(&digest),
+        })
+    }
+
+    /// Ensure a file on disk hashes to this checksum
+    pub fn verify(&self, pth: &Path) -> LalResult<()> {
+        let actual = Checksum::of_file(self.kind, pth)?;
+        if actual != *self {
+            return Err(CliError::ChecksumMismatch(pth.display().to_string(),
+                                                  self.to_string(),
+                                                  actual.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Checksum from the `X-Checksum-*` headers Artifactory sends with a download
+    pub fn from_headers(headers: &Headers) -> Option<Checksum> {
+        let candidates = [(HashType::Sha256, "X-Checksum-Sha256"),
+                          (HashType::Sha1, "X-Checksum-Sha1")];
+        for &(kind, name) in &candidates {
+            let value = headers.get_raw(name)
+                .and_then(|raw| raw.first())
+                .and_then(|v| String::from_utf8(v.clone()).ok());
+            if let Some(c) = value.and_then(|v| Checksum::parse(kind, &v)) {
+                return Some(c);
+            }
+        }
+        None
+    }
+
+    /// Read a recorded sidecar checksum for a file (preferring sha256)
+    pub fn read_sidecar(pth: &Path) -> LalResult<Option<Checksum>> {
+        for kind in &[HashType::Sha256, HashType::Sha1] {
+            let sidecar = sidecar_path(pth, *kind);
+            if sidecar.is_file() {
+                let mut body = String::new();
+                File::open(&sidecar)?.read_to_string(&mut body)?;
+                return Ok(Checksum::parse(*kind, &body));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Contents of a `sha256sum` compatible sidecar for a file name
+    pub fn sidecar_contents(&self, filename: &str) -> String {
+        format!("{}  {}\n", self.hex, filename)
+    }
+
+    /// Record this checksum in a sidecar next to a file
+    pub fn write_sidecar(&self, pth: &Path) -> LalResult<()> {
+        let filename = pth.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let mut f = File::create(sidecar_path(pth, self.kind))?;
+        write!(f, "{}", self.sidecar_contents(filename))?;
+        f.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Verify a download against its expected checksum (if the server provided one)
+///
+/// A file that fails verification is removed so it never makes it into the cache.
+pub fn verify_download(expected: Option<Checksum>, pth: &Path, source: &str) -> LalResult<()> {
+    if let Some(sum) = expected {
+        debug!("Verifying {} against {}", pth.display(), sum);
+        if let Err(e) = sum.verify(pth) {
+            let _ = fs::remove_file(pth);
+            return Err(e);
+        }
+    } else {
+        warn!("No checksum available for {} - download not verified", source);
+    }
+    Ok(())
+}
@@ -1,43 +1,109 @@
-use std::fs;
+use std::cmp;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crossbeam;
+use filetime::{FileTime, set_file_times};
 
 use storage::{Backend, CachedBackend, Component};
-use core::{CliError, LalResult, output};
+use super::checksum::{Checksum, HashType};
+use super::lock::CacheLock;
+use core::{CliError, LalResult, Lockfile, Version, output};
+
+fn is_cached(cache: &str, name: &str, version: &Version, env: &str) -> bool {
+    // the tarball is renamed into place last, so if it is there the entry is complete
+    get_cache_dir(cache, name, version, env).join(format!("{}.tar.gz", name)).is_file()
+}
 
-fn is_cached<T: Backend + ?Sized>(backend: &T, name: &str, version: u32, env: &str) -> bool {
-    get_cache_dir(backend, name, version, env).is_dir()
+fn get_cache_dir(cache: &str, name: &str, version: &Version, env: &str) -> PathBuf {
+    Path::new(cache).join("environments").join(env).join(name).join(version.to_string())
+}
+
+// record a use of a cache entry for `lal clean --max-size` (by bumping its mtime)
+fn mark_used(dir: &Path) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    let time = FileTime::from_seconds_since_1970(now.as_secs(), now.subsec_nanos());
+    if let Err(e) = set_file_times(dir, time, time) {
+        // read-only shared caches are fine - they just don't get LRU information
+        debug!("Failed to update last use of {}: {}", dir.display(), e);
+    }
 }
 
-fn get_cache_dir<T: Backend + ?Sized>(backend: &T, name: &str, version: u32, env: &str) -> PathBuf {
-    let cache = backend.get_cache_dir();
-    Path::new(&cache).join("environments").join(env).join(name).join(version.to_string())
+// where downloads go before being moved into the cache (same filesystem for the rename)
+fn get_partial_dir(cache: &str, name: &str, version: &Version, env: &str) -> PathBuf {
+    Path::new(cache).join("partial").join(env).join(name).join(version.to_string())
 }
 
-fn store_tarball<T: Backend + ?Sized>(
-    backend: &T,
+fn store_tarball(
+    cache: &str,
+    src: &Path,
     name: &str,
-    version: u32,
+    version: &Version,
     env: &str,
 ) -> Result<(), CliError> {
     // 1. mkdir -p cacheDir/$name/$version
-    let destdir = get_cache_dir(backend, name, version, env);
+    let destdir = get_cache_dir(cache, name, version, env);
     if !destdir.is_dir() {
         fs::create_dir_all(&destdir)?;
     }
-    // 2. stuff $PWD/$name.tar.gz in there
-    let tarname = [name, ".tar.gz"].concat();
-    let dest = Path::new(&destdir).join(&tarname);
-    let src = Path::new(".").join(&tarname);
+    let dest = destdir.join([name, ".tar.gz"].concat());
     if !src.is_file() {
         return Err(CliError::MissingTarball);
     }
+
+    // 2. record its checksum for `lal cache verify`
+    Checksum::of_file(HashType::Sha256, src)?.write_sidecar(&dest)?;
+
+    // 3. flush it to disk, then atomically move it in
+    File::open(src)?.sync_all()?;
     debug!("Move {:?} -> {:?}", src, dest);
-    fs::copy(&src, &dest)?;
-    fs::remove_file(&src)?;
+    fs::rename(src, &dest)?;
+    File::open(&destdir)?.sync_all()?; // persist the rename itself
 
     Ok(())
 }
 
+/// Add a tarball to a cache directory as a published component
+///
+/// This goes through the same lock and atomic rename as a download,
+/// and replaces any tarball already cached for that component version.
+pub fn import_tarball(
+    cache: &str,
+    tarball: &Path,
+    name: &str,
+    version: &Version,
+    env: &str,
+) -> LalResult<()> {
+    if !tarball.is_file() {
+        return Err(CliError::MissingTarball);
+    }
+    let partial = get_partial_dir(cache, name, version, env);
+    fs::create_dir_all(&partial)?;
+    let _lock = CacheLock::acquire(&partial.join("lock"))?;
+    if is_cached(cache, name, version, env) {
+        warn!("Replacing cached {}={} in {}", name, version, env);
+    }
+    let staged = partial.join(format!("{}.tar.gz", name));
+    fs::copy(tarball, &staged)?;
+    store_tarball(cache, &staged, name, version, env)
+}
+
+/// Path of a published component's tarball in a cache directory (if it is cached)
+pub fn cached_tarball(
+    cache: &str,
+    name: &str,
+    version: &Version,
+    env: &str,
+) -> Option<PathBuf> {
+    if is_cached(cache, name, version, env) {
+        Some(get_cache_dir(cache, name, version, env).join(format!("{}.tar.gz", name)))
+    } else {
+        None
+    }
+}
+
 // helper for the unpack_ functions
 fn extract_tarball_to_input(tarname: PathBuf, component: &str) -> LalResult<()> {
     use tar::Archive;
@@ -81,13 +147,13 @@ where
 {
     /// Get the latest versions of a component across all supported environments
     ///
-    /// Because the versions have to be available in all environments, these numbers may
-    /// not contain the highest numbers available on specific environments.
+    /// Because the versions have to be available in all environments, these versions may
+    /// not contain the highest versions available on specific environments.
     fn get_latest_supported_versions(
         &self,
         name: &str,
         environments: Vec<String>,
-    ) -> LalResult<Vec<u32>> {
+    ) -> LalResult<Vec<Version>> {
         use std::collections::BTreeSet;
         let mut result = BTreeSet::new();
         let mut first_pass = true;
@@ -110,33 +176,72 @@ where
     fn retrieve_published_component(
         &self,
         name: &str,
-        version: Option<u32>,
+        version: Option<Version>,
         env: &str,
     ) -> LalResult<(PathBuf, Component)> {
         trace!("Locate component {}", name);
 
         let component = self.get_component_info(name, version, env)?;
+        let cache = self.get_cache_dir();
 
-        if !is_cached(self, &component.name, component.version, env) {
-            // download to PWD then move it to stash immediately
-            let local_tarball = Path::new(".").join(format!("{}.tar.gz", name));
-            self.raw_fetch(&component.location, &local_tarball)?;
-            store_tarball(self, name, component.version, env)?;
+        if !is_cached(&cache, &component.name, &component.version, env) {
+            // download inside the cache so that it can be renamed into place when complete
+            // a partial download from an interrupted run is resumed where supported
+            let partial = get_partial_dir(&cache, name, &component.version, env);
+            fs::create_dir_all(&partial)?;
+            let _lock = CacheLock::acquire(&partial.join("lock"))?;
+            // another lal process may have cached it while we were waiting
+            if !is_cached(&cache, &component.name, &component.version, env) {
+                let tarball = partial.join(format!("{}.tar.gz", name));
+                self.raw_fetch(&component.location, &tarball)?;
+                store_tarball(&cache, &tarball, name, &component.version, env)?;
+            }
+        } else {
+            mark_used(&get_cache_dir(&cache, &component.name, &component.version, env));
         }
-        assert!(is_cached(self, &component.name, component.version, env),
+        assert!(is_cached(&cache, &component.name, &component.version, env),
                 "cached component");
 
         trace!("Fetching {} from cache", name);
-        let tarname = get_cache_dir(self, &component.name, component.version, env)
+        let tarname = get_cache_dir(&cache, &component.name, &component.version, env)
             .join(format!("{}.tar.gz", name));
         Ok((tarname, component))
     }
 
+    /// Fetch the published lockfile of a component, caching it next to its tarball
+    ///
+    /// Published versions never change, so a cached lockfile is always reused.
+    fn retrieve_published_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile> {
+        let cache = self.get_cache_dir();
+        let dir = get_cache_dir(&cache, name, version, env);
+        let lock_path = dir.join("lockfile.json");
+        if lock_path.is_file() {
+            mark_used(&dir);
+            return Lockfile::from_path(&lock_path, name);
+        }
+        let lf = self.get_component_lockfile(name, version, env)?;
+
+        // write it next to where the tarball goes, then atomically move it in
+        let partial = get_partial_dir(&cache, name, version, env);
+        fs::create_dir_all(&partial)?;
+        let _lock = CacheLock::acquire(&partial.join("lock"))?;
+        let tmp = partial.join("lockfile.json");
+        lf.write(&tmp)?;
+        fs::create_dir_all(&dir)?;
+        fs::rename(&tmp, &lock_path)?;
+        Ok(lf)
+    }
+
     // basic functionality for `fetch`/`update`
     fn unpack_published_component(
         &self,
         name: &str,
-        version: Option<u32>,
+        version: Option<Version>,
         env: &str,
     ) -> LalResult<Component> {
         let (tarname, component) = self.retrieve_published_component(name, version, env)?;
@@ -149,6 +254,73 @@ where
         Ok(component)
     }
 
+    // parallel `fetch`/`update`
+    fn unpack_published_components(
+        &self,
+        components: &[(String, Version)],
+        env: &str,
+        jobs: usize,
+    ) -> Vec<LalResult<Component>> {
+        let jobs = cmp::max(1, cmp::min(jobs, components.len()));
+        if jobs == 1 {
+            return components
+                .iter()
+                .map(|&(ref name, ref version)| {
+                    info!("Fetch {} {} {}", env, name, version);
+                    self.unpack_published_component(name, Some(version.clone()), env)
+                })
+                .collect();
+        }
+        debug!("Fetching {} components with {} jobs", components.len(), jobs);
+
+        // one aggregate bar rather than interleaved download bars
+        #[cfg(feature = "progress")]
+        let pb = {
+            use indicatif::{ProgressBar, ProgressStyle};
+            use super::progress::hide_file_bars;
+            hide_file_bars(true);
+            let pb = ProgressBar::new(components.len() as u64);
+            pb.set_style(ProgressStyle::default_bar()
+                             .template("{bar:40.yellow/black} {pos}/{len} components {msg}"));
+            pb
+        };
+
+        let queue = Mutex::new(components.iter().enumerate());
+        let results = Mutex::new(components.iter().map(|_| None).collect::<Vec<_>>());
+        crossbeam::scope(|scope| {
+            for _ in 0..jobs {
+                let queue = &queue;
+                let results = &results;
+                #[cfg(feature = "progress")]
+                let pb = &pb;
+                scope.spawn(move || loop {
+                    let next = queue.lock().unwrap().next();
+                    let (i, &(ref name, ref version)) = match next {
+                        Some(n) => n,
+                        None => break,
+                    };
+                    info!("Fetch {} {} {}", env, name, version);
+                    let res = self.unpack_published_component(name, Some(version.clone()), env);
+                    results.lock().unwrap()[i] = Some(res);
+                    #[cfg(feature = "progress")]
+                    {
+                        pb.set_message(name);
+                        pb.inc(1);
+                    }
+                });
+            }
+        });
+
+        #[cfg(feature = "progress")]
+        {
+            use super::progress::hide_file_bars;
+            pb.finish_and_clear();
+            hide_file_bars(false);
+        }
+        // every slot is filled once the scope has joined all the threads
+        results.into_inner().unwrap().into_iter().map(|r| r.unwrap()).collect()
+    }
+
     /// helper for `update`
     fn unpack_stashed_component(&self, name: &str, code: &str) -> LalResult<()> {
         let tarpath = self.retrieve_stashed_component(name, code)?;
@@ -167,6 +339,9 @@ where
         if !tarpath.is_file() {
             return Err(CliError::MissingStashArtifact(format!("{}/{}", name, code)));
         }
+        if let Some(dir) = tarpath.parent() {
+            mark_used(dir);
+        }
         Ok(tarpath)
     }
 
@@ -177,7 +352,9 @@ where
         fs::create_dir_all(&destdir)?;
 
         // Tar it straight into destination
-        output::tar(&destdir.join(format!("{}.tar.gz", name)))?;
+        let tarpath = destdir.join(format!("{}.tar.gz", name));
+        output::tar(&tarpath)?;
+        Checksum::of_file(HashType::Sha256, &tarpath)?.write_sidecar(&tarpath)?;
 
         // Copy the lockfile there for users inspecting the stashed folder
         // NB: this is not really needed, as it's included in the tarball anyway
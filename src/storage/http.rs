@@ -0,0 +1,230 @@
+#![allow(missing_docs)]
+
+use std::vec::Vec;
+use std::io::Read;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+use regex::Regex;
+use hyper::method::Method;
+use hyper::header::{Authorization, Basic};
+use hyper::status::StatusCode;
+
+use core::{CliError, LalResult, Lockfile, Version};
+use super::artifactory::Credentials;
+use super::checksum::{Checksum, HashType};
+use super::network::NetworkOptions;
+
+
+/// Plain HTTP server locations (nginx autoindex or WebDAV)
+///
+/// The server is expected to expose the same `env/<env>/<name>/<version>/`
+/// tree that the `ArtifactoryBackend` publishes into.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct HttpConfig {
+    /// Base url of the artifact tree (the parent of the `env` folder)
+    pub url: String,
+    /// Optional publish credentials (sent as http basic auth)
+    pub credentials: Option<Credentials>,
+    /// Timeouts and retries for downloads
+    #[serde(default)]
+    pub network: NetworkOptions,
+}
+
+// nginx `autoindex_format json;` entries - we only care about folder names
+#[derive(Deserialize)]
+struct HttpIndexEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+/// Parse a directory listing into a list of versions
+///
+/// Accepts either a JSON index (as served by nginx with `autoindex_format json`),
+/// or a plain html autoindex page where versions are linked to as `href="12/"`.
+/// Folders that are not valid versions are ignored.
+fn parse_versions(body: &str) -> Vec<Version> {
+    let index: Result<Vec<HttpIndexEntry>, _> = serde_json::from_str(body);
+    let mut versions: Vec<Version> = if let Ok(idx) = index {
+        idx.into_iter()
+            .filter(|e| e.kind.as_ref().map(|k| k == "directory").unwrap_or(true))
+            .filter_map(|e| e.name.trim_matches('/').parse().ok())
+            .collect()
+    } else {
+        let re = Regex::new(r#"href="(?:\./)?(\d[0-9A-Za-z.+-]*)/""#).unwrap();
+        re.captures_iter(body).filter_map(|c| c.at(1)).filter_map(|v| v.parse().ok()).collect()
+    };
+    versions.sort_by(|a, b| b.cmp(a)); // sort by version descending
+    versions.dedup();
+    versions
+}
+
+/// Upload a file to the http server with a PUT
+///
+/// WebDAV servers generally need the parent collections to exist first,
+/// so these are created with MKCOL (failures ignored as they usually exist).
+fn upload_file(cfg: &HttpConfig, uri: &str, body: &[u8]) -> LalResult<()> {
+    let client = cfg.network.client();
+    let auth = cfg.credentials.clone().map(|creds| {
+        Authorization(Basic {
+                          username: creds.username,
+                          password: Some(creds.password),
+                      })
+    });
+
+    // create parent collections
+    let segments: Vec<&str> = uri.split('/').collect();
+    for i in 1..segments.len() {
+        let col_uri = format!("{}/{}/", cfg.url, segments[..i].join("/"));
+        let mut req = client.request(Method::Extension("MKCOL".into()), &col_uri[..]);
+        if let Some(a) = auth.clone() {
+            req = req.header(a);
+        }
+        match req.send() {
+            Ok(resp) => debug!("{} from MKCOL {}", resp.status, col_uri),
+            Err(e) => debug!("MKCOL {} failed: {}", col_uri, e),
+        }
+    }
+
+    let full_uri = format!("{}/{}", cfg.url, uri);
+    info!("PUT {}", full_uri);
+    let mut req = client.put(&full_uri[..]).body(body);
+    if let Some(a) = auth {
+        req = req.header(a);
+    }
+    let resp = req.send()?;
+    debug!("resp={:?}", resp);
+    let respstr = format!("{} from PUT {}", resp.status, full_uri);
+    match resp.status {
+        StatusCode::Created | StatusCode::NoContent | StatusCode::Ok => {
+            debug!("{}", respstr);
+            Ok(())
+        }
+        _ => Err(CliError::UploadFailure(respstr)),
+    }
+}
+
+use super::{Backend, Component};
+
+/// Artifact storage on a plain HTTP server
+pub struct HttpBackend {
+    /// Http server config
+    pub config: HttpConfig,
+    /// Cache directory
+    pub cache: String,
+}
+
+impl HttpBackend {
+    pub fn new(cfg: &HttpConfig, cache: &str) -> Self {
+        HttpBackend {
+            config: cfg.clone(),
+            cache: cache.into(),
+        }
+    }
+
+    // The URL for a component tarball under the one of the environment trees
+    fn get_tarball_url(&self, name: &str, version: &Version, env: &str) -> String {
+        let tar_url = format!("{}/env/{}/{}/{}/{}.tar.gz",
+                              self.config.url,
+                              env,
+                              name,
+                              version,
+                              name);
+        trace!("Inferring tarball location as {}", tar_url);
+        tar_url
+    }
+
+    // The URL for the lockfile published next to a component tarball
+    fn get_lockfile_url(&self, name: &str, version: &Version, env: &str) -> String {
+        format!("{}/env/{}/{}/{}/lockfile.json", self.config.url, env, name, version)
+    }
+}
+
+/// Artifact backend trait for `HttpBackend`
+///
+/// This is intended to be used by the caching trait `CachedBackend`, but for
+/// specific low-level use cases, these methods can be used directly.
+impl Backend for HttpBackend {
+    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<Version>> {
+        let url = format!("{}/env/{}/{}/", self.config.url, loc, name);
+        debug!("GET {}", url);
+        let resp = self.config
+            .network
+            .get_string(&url)
+            .map_err(|e| {
+                warn!("Failed to GET {}: {}", url, e);
+                CliError::BackendFailure("No version information found on http index".into())
+            })?;
+        trace!("Got body {}", resp);
+        Ok(parse_versions(&resp))
+    }
+
+    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<Version> {
+        if let Some(latest) = self.get_versions(name, loc)?.into_iter().max() {
+            return Ok(latest);
+        }
+        Err(CliError::BackendFailure("No version information found on http index".into()))
+    }
+
+    fn get_component_info(
+        &self,
+        name: &str,
+        version: Option<Version>,
+        loc: &str,
+    ) -> LalResult<Component> {
+        let v = if let Some(ver) = version {
+            ver
+        } else {
+            self.get_latest_version(name, loc)?
+        };
+        Ok(Component {
+            name: name.into(),
+            location: self.get_tarball_url(name, &v, loc),
+            version: v,
+        })
+    }
+
+    fn publish_artifact(&self, name: &str, version: &Version, env: &str) -> LalResult<()> {
+        // this fn basically assumes all the sanity checks have been performed
+        // files must exist and lockfile must be sensible
+        let artdir = Path::new("./ARTIFACT");
+        let tarball = artdir.join(format!("{}.tar.gz", name));
+        let lockfile = artdir.join("lockfile.json");
+
+        let tar_uri = format!("env/{}/{}/{}/{}.tar.gz", env, name, version, name);
+        let mut tar_data = vec![];
+        File::open(&tarball)?.read_to_end(&mut tar_data)?;
+        upload_file(&self.config, &tar_uri, &tar_data)?;
+
+        // checksum sidecar so downloads can be verified
+        let sum = Checksum::of_file(HashType::Sha256, &tarball)?;
+        let sum_uri = format!("{}.{}", tar_uri, sum.kind.extension());
+        let sum_data = sum.sidecar_contents(&format!("{}.tar.gz", name));
+        upload_file(&self.config, &sum_uri, sum_data.as_bytes())?;
+
+        let lf_uri = format!("env/{}/{}/{}/lockfile.json", env, name, version);
+        let mut lf_data = vec![];
+        File::open(lockfile)?.read_to_end(&mut lf_data)?;
+        upload_file(&self.config, &lf_uri, &lf_data)?;
+        Ok(())
+    }
+
+    fn get_cache_dir(&self) -> String { self.cache.clone() }
+
+    fn raw_fetch(&self, url: &str, dest: &PathBuf) -> LalResult<()> {
+        self.config.network.download(url, dest)
+    }
+
+    fn get_component_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile> {
+        let url = self.get_lockfile_url(name, version, env);
+        trace!("Fetching lockfile from {}", url);
+        Ok(serde_json::from_str(&self.config.network.get_string(&url)?)?)
+    }
+}
@@ -1,18 +1,23 @@
 #![allow(missing_docs)]
 
 use std::fs;
-use std::str::FromStr;
 use std::vec::Vec;
 use std::path::{Path, PathBuf};
 
-use core::{CliError, LalResult, config_dir, ensure_dir_exists_fresh};
+use core::{CliError, LalResult, Lockfile, Version, config_dir, ensure_dir_exists_fresh};
 
 
-/// LocalBackend configuration options (currently none)
+/// LocalBackend configuration options
 #[derive(Serialize, Deserialize, Clone, Default)]
-pub struct LocalConfig {}
+pub struct LocalConfig {
+    /// Directory to store artifacts in (defaults to the cache directory)
+    ///
+    /// Pointing this at a shared directory gives a team-local mirror.
+    pub path: Option<String>,
+}
 
 use super::{Backend, Component};
+use super::checksum::{Checksum, HashType};
 
 /// Artifact storage on the local machine
 pub struct LocalBackend {
@@ -29,6 +34,11 @@ impl LocalBackend {
             cache: cache.into(),
         }
     }
+
+    // Base directory of the `environments` tree
+    fn storage_dir(&self) -> String {
+        self.config.path.clone().unwrap_or_else(|| self.cache.clone())
+    }
 }
 
 /// Artifact backend trait for `LocalBackend`
@@ -36,24 +46,29 @@ impl LocalBackend {
 /// This is intended to be used by the caching trait `CachedBackend`, but for
 /// specific low-level use cases, these methods can be used directly.
 impl Backend for LocalBackend {
-    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<u32>> {
-        let tar_dir = format!("{}/environments/{}/{}/", self.cache, loc, name);
+    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<Version>> {
+        let tar_dir = format!("{}/environments/{}/{}/", self.storage_dir(), loc, name);
         let dentries = fs::read_dir(config_dir().join(tar_dir));
         let mut versions = vec![];
         for entry in dentries? {
             let path = entry?;
             if let Some(filename) = path.file_name().to_str() {
-                if let Ok(version) = u32::from_str(filename) {
-                    versions.push(version);
+                // a cached lockfile alone does not make a version available
+                let tarball = path.path().join(format!("{}.tar.gz", name));
+                if let Ok(version) = filename.parse::<Version>() {
+                    if tarball.is_file() {
+                        versions.push(version);
+                    }
                 }
             }
         }
+        versions.sort_by(|a, b| b.cmp(a)); // descending
         Ok(versions)
     }
 
-    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<u32> {
-        if let Some(&last) = self.get_versions(name, loc)?.last() {
-            return Ok(last);
+    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<Version> {
+        if let Some(latest) = self.get_versions(name, loc)?.into_iter().next() {
+            return Ok(latest);
         }
         Err(CliError::BackendFailure("No versions found on local storage".into()))
     }
@@ -61,7 +76,7 @@ impl Backend for LocalBackend {
     fn get_component_info(
         &self,
         name: &str,
-        version: Option<u32>,
+        version: Option<Version>,
         loc: &str,
     ) -> LalResult<Component> {
         info!("get_component_info: {} {:?} {}", name, version, loc);
@@ -71,7 +86,12 @@ impl Backend for LocalBackend {
         } else {
             self.get_latest_version(name, loc)?
         };
-        let loc = format!("{}/environments/{}/{}/{}/{}.tar.gz", self.cache, loc, name, v, name);
+        let loc = format!("{}/environments/{}/{}/{}/{}.tar.gz",
+                          self.storage_dir(),
+                          loc,
+                          name,
+                          v,
+                          name);
         Ok(Component {
             name: name.into(),
             version: v,
@@ -79,7 +99,7 @@ impl Backend for LocalBackend {
         })
     }
 
-    fn publish_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()> {
+    fn publish_artifact(&self, name: &str, version: &Version, env: &str) -> LalResult<()> {
         // this fn basically assumes all the sanity checks have been performed
         // files must exist and lockfile must be sensible
         let artifactdir = Path::new("./ARTIFACT");
@@ -87,16 +107,19 @@ impl Backend for LocalBackend {
         let lockfile = artifactdir.join("lockfile.json");
 
         // prefix with environment
-        let tar_dir = format!("{}/environments/{}/{}/{}/", self.cache, env, name, version);
-        let tar_path = format!("{}/environments/{}/{}/{}/{}.tar.gz", self.cache, env, name, version, name);
-        let lock_path = format!("{}/environments/{}/{}/{}/lockfile.json", self.cache, env, name, version);
+        let dir = self.storage_dir();
+        let tar_dir = format!("{}/environments/{}/{}/{}/", dir, env, name, version);
+        let tar_path = format!("{}/environments/{}/{}/{}/{}.tar.gz", dir, env, name, version, name);
+        let lock_path = format!("{}/environments/{}/{}/{}/lockfile.json", dir, env, name, version);
 
         if let Some(full_tar_dir) = config_dir().join(tar_dir).to_str() {
             ensure_dir_exists_fresh(full_tar_dir)?;
         }
 
-        fs::copy(tarball, config_dir().join(tar_path))?;
+        let full_tar_path = config_dir().join(tar_path);
+        fs::copy(tarball, &full_tar_path)?;
         fs::copy(lockfile, config_dir().join(lock_path))?;
+        Checksum::of_file(HashType::Sha256, &full_tar_path)?.write_sidecar(&full_tar_path)?;
 
         Ok(())
     }
@@ -106,6 +129,23 @@ impl Backend for LocalBackend {
     fn raw_fetch(&self, src: &str, dest: &PathBuf) -> LalResult<()> {
         debug!("raw fetch {} -> {}", src, dest.display());
         fs::copy(src, dest)?;
+        if let Some(sum) = Checksum::read_sidecar(Path::new(src))? {
+            sum.verify(dest)?;
+        }
         Ok(())
     }
+
+    fn get_component_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile> {
+        let lock_path = format!("{}/environments/{}/{}/{}/lockfile.json",
+                                self.storage_dir(),
+                                env,
+                                name,
+                                version);
+        Lockfile::from_path(&PathBuf::from(lock_path), name)
+    }
 }
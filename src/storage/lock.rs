@@ -0,0 +1,53 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use libc;
+
+use core::LalResult;
+
+/// An exclusive advisory lock on a file in the cache
+///
+/// Held while a cache entry is being written so that concurrent `lal` processes
+/// (or threads of a parallel fetch) do not race on it. Released when dropped.
+pub struct CacheLock {
+    file: File,
+}
+
+impl CacheLock {
+    /// Block until the lock at the given path is acquired (creating the file if needed)
+    pub fn acquire(pth: &Path) -> LalResult<CacheLock> {
+        let file = OpenOptions::new().write(true).create(true).open(pth)?;
+        let fd = file.as_raw_fd();
+        if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            info!("Waiting for another lal process to release {}", pth.display());
+            if unsafe { libc::flock(fd, libc::LOCK_EX) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        trace!("Locked {}", pth.display());
+        Ok(CacheLock { file: file })
+    }
+
+    /// Acquire the lock at the given path only if nothing else holds it
+    pub fn try_acquire(pth: &Path) -> LalResult<Option<CacheLock>> {
+        let file = OpenOptions::new().write(true).create(true).open(pth)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(err.into());
+        }
+        trace!("Locked {}", pth.display());
+        Ok(Some(CacheLock { file: file }))
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        // closing the file would release it anyway - this just makes it explicit
+        unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
@@ -2,6 +2,14 @@ pub use self::traits::{BackendConfiguration, Backend, CachedBackend, Component};
 
 pub use self::artifactory::{ArtifactoryConfig, Credentials, ArtifactoryBackend};
 pub use self::local::{LocalConfig, LocalBackend};
+pub use self::http::{HttpConfig, HttpBackend};
+pub use self::s3::{S3Config, S3Credentials, S3Backend};
+pub use self::chain::{ChainConfig, ChainBackend};
+pub use self::offline::OfflineBackend;
+pub use self::checksum::{Checksum, HashType, to_hex};
+pub use self::network::NetworkOptions;
+pub use self::download::{cached_tarball, import_tarball};
+pub use self::lock::CacheLock;
 
 // Some special exports for lal upgrade - canonical releases are on artifactory atm
 #[cfg(feature = "upgrade")]
@@ -10,7 +18,14 @@ pub use self::artifactory::{LatestLal, get_latest_lal_version, http_download_to_
 mod traits;
 mod artifactory;
 mod local;
+mod http;
+mod s3;
+mod chain;
+mod offline;
 mod download;
+mod checksum;
+mod network;
+mod lock;
 
 #[cfg(feature = "progress")]
 mod progress;
@@ -0,0 +1,273 @@
+use std::io::{self, Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use hyper::{self, Client};
+use hyper::client::Response;
+use hyper::net::{HttpStream, HttpsConnector, NetworkConnector};
+use hyper::header::{ContentLength, Range, ByteRangeSpec};
+use hyper::status::StatusCode;
+use hyper_native_tls::NativeTlsClient;
+
+use core::{CliError, LalResult};
+use super::checksum::{Checksum, HashType, verify_download};
+
+/// Timeouts and retry policy for http transfers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct NetworkOptions {
+    /// Seconds to wait for a connection to be established
+    pub connect_timeout: u64,
+    /// Seconds to wait for data on an established connection
+    pub read_timeout: u64,
+    /// Seconds to wait for a request to be sent on an established connection
+    pub write_timeout: u64,
+    /// Number of retries after connection errors or 5xx responses
+    pub retries: u32,
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        NetworkOptions {
+            connect_timeout: 10,
+            read_timeout: 60,
+            write_timeout: 60,
+            retries: 3,
+        }
+    }
+}
+
+// plain tcp connector with a timeout on connect (hyper's HttpConnector has none)
+struct TimeoutConnector(Duration);
+
+impl NetworkConnector for TimeoutConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+        if scheme != "http" {
+            let err = io::Error::new(io::ErrorKind::InvalidInput, "Invalid scheme for Http");
+            return Err(err.into());
+        }
+        let mut last_err = io::Error::new(io::ErrorKind::AddrNotAvailable, host.to_string());
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.0) {
+                Ok(s) => return Ok(HttpStream(s)),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(last_err.into())
+    }
+}
+
+// io errors on the connection, as opposed to on local files
+fn network_error(e: io::Error) -> CliError {
+    CliError::Hype(hyper::Error::Io(e))
+}
+
+// errors that are worth another attempt (local disk errors are not)
+fn is_transient(e: &CliError) -> bool {
+    match *e {
+        CliError::Hype(hyper::Error::Io(_)) => true,
+        _ => false,
+    }
+}
+
+// copy a response body into a file, reporting the bytes copied so far
+fn copy_body<F: FnMut(u64)>(res: &mut Response, f: &mut File, mut progress: F) -> LalResult<u64> {
+    let mut buffer = [0; 1024 * 64];
+    let mut copied = 0;
+    loop {
+        let read = res.read(&mut buffer).map_err(network_error)?;
+        if read == 0 {
+            return Ok(copied);
+        }
+        f.write_all(&buffer[0..read])?;
+        copied += read as u64;
+        progress(copied);
+    }
+}
+
+// write the body of a GET response to a file (appending if it resumes at `offset`)
+//
+// Returns the checksum advertised in the response headers if any.
+fn write_response(mut res: Response, save: &Path, offset: u64) -> LalResult<Option<Checksum>> {
+    let resumed = offset > 0 && res.status == StatusCode::PartialContent;
+    if res.status != hyper::Ok && !resumed {
+        return Err(CliError::BackendFailure(format!("GET request with {}", res.status)));
+    }
+    let checksum = Checksum::from_headers(&res.headers);
+    let start = if resumed { offset } else { 0 };
+    let total = res.headers.get::<ContentLength>().map(|l| l.0 + start);
+    let mut f = if resumed {
+        debug!("Resuming download of {} at {} bytes", save.display(), offset);
+        OpenOptions::new().append(true).open(save)?
+    } else {
+        File::create(save)?
+    };
+
+    let mut downloaded = start;
+    if cfg!(feature = "progress") {
+        #[cfg(feature = "progress")]
+        {
+            use indicatif::ProgressStyle;
+            use super::progress::{file_bar, file_spinner};
+            let pb = if let Some(len) = total {
+                let pb = file_bar(len);
+                pb.set_style(ProgressStyle::default_bar()
+                                 .template("{bar:40.yellow/black} {bytes}/{total_bytes} ({eta})"));
+                pb
+            } else {
+                // no Content-Length - can only show what we have so far
+                file_spinner()
+            };
+            pb.set_position(downloaded);
+            downloaded += copy_body(&mut res, &mut f, |n| pb.set_position(start + n))?;
+            pb.finish();
+        }
+    } else {
+        downloaded += copy_body(&mut res, &mut f, |_| ())?;
+    }
+    f.flush()?;
+
+    if let Some(len) = total {
+        if downloaded < len {
+            let reason = format!("connection closed after {}/{} bytes", downloaded, len);
+            return Err(network_error(io::Error::new(io::ErrorKind::UnexpectedEof, reason)));
+        }
+    }
+    Ok(checksum)
+}
+
+impl NetworkOptions {
+    /// A hyper client with the configured timeouts
+    pub fn client(&self) -> Client {
+        let ssl = NativeTlsClient::new().unwrap();
+        let tcp = TimeoutConnector(Duration::from_secs(self.connect_timeout));
+        let mut client = Client::with_connector(HttpsConnector::with_connector(ssl, tcp));
+        client.set_read_timeout(Some(Duration::from_secs(self.read_timeout)));
+        client.set_write_timeout(Some(Duration::from_secs(self.write_timeout)));
+        client
+    }
+
+    // exponential backoff between attempts: 1s, 2s, 4s, ...
+    fn backoff(&self, attempt: u32, what: &str, e: &CliError) -> bool {
+        if attempt >= self.retries {
+            return false;
+        }
+        let delay: u64 = 1 << attempt;
+        warn!("{} failed ({}) - retrying in {}s", what, e, delay);
+        thread::sleep(Duration::from_secs(delay));
+        true
+    }
+
+    /// GET a url as a string, retrying on connection errors and 5xx responses
+    pub fn get_string(&self, url: &str) -> LalResult<String> {
+        let client = self.client();
+        let mut attempt = 0;
+        loop {
+            let err = match client.get(url).send() {
+                Ok(ref res) if res.status.is_server_error() => {
+                    CliError::BackendFailure(format!("GET request with {}", res.status))
+                }
+                Ok(mut res) => {
+                    if res.status != hyper::Ok {
+                        return Err(CliError::BackendFailure(format!("GET request with {}",
+                                                                    res.status)));
+                    }
+                    let mut body = String::new();
+                    match res.read_to_string(&mut body) {
+                        Ok(_) => return Ok(body),
+                        Err(e) => CliError::Io(e),
+                    }
+                }
+                Err(hyper::Error::Io(e)) => CliError::Io(e),
+                Err(e) => return Err(e.into()),
+            };
+            if !self.backoff(attempt, &format!("GET {}", url), &err) {
+                return Err(err);
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Download into a file with retries, resuming partial downloads
+    ///
+    /// `request` must send the GET, adding a `Range` header when given a non-zero offset.
+    /// An existing file at `save` is treated as a partial download and resumed.
+    /// Returns the checksum advertised by the server (if any).
+    pub fn download_with<F>(
+        &self,
+        url: &str,
+        save: &Path,
+        mut request: F,
+    ) -> LalResult<Option<Checksum>>
+    where
+        F: FnMut(u64) -> LalResult<Response>,
+    {
+        let mut attempt = 0;
+        loop {
+            let offset = if save.is_file() { fs::metadata(save)?.len() } else { 0 };
+            let (err, transient) = match request(offset) {
+                Ok(ref res) if res.status.is_server_error() => {
+                    (CliError::BackendFailure(format!("GET request with {}", res.status)), true)
+                }
+                Ok(ref res) if res.status == StatusCode::RangeNotSatisfiable => {
+                    // partial file is not a prefix of what is there now - start over
+                    fs::remove_file(save)?;
+                    (CliError::BackendFailure(format!("GET request with {}", res.status)), true)
+                }
+                Ok(res) => {
+                    match write_response(res, save, offset) {
+                        Ok(sum) => return Ok(sum),
+                        Err(e) => {
+                            let transient = is_transient(&e);
+                            (e, transient)
+                        }
+                    }
+                }
+                Err(e) => {
+                    let transient = is_transient(&e);
+                    (e, transient)
+                }
+            };
+            if !transient || !self.backoff(attempt, &format!("GET {}", url), &err) {
+                return Err(err);
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Download a url into a file and verify it
+    ///
+    /// Verification uses the checksum header or a `.sha256`/`.sha1` sidecar on the server.
+    pub fn download(&self, url: &str, save: &Path) -> LalResult<()> {
+        debug!("GET {}", url);
+        let client = self.client();
+        let header_sum = self.download_with(url, save, |offset| {
+            let mut req = client.get(url);
+            if offset > 0 {
+                req = req.header(Range::Bytes(vec![ByteRangeSpec::AllFrom(offset)]));
+            }
+            Ok(req.send()?)
+        })?;
+        let expected = header_sum.or_else(|| self.get_checksum_sidecar(url));
+        verify_download(expected, save, url)
+    }
+
+    // fetch a `.sha256` or `.sha1` sidecar for a url if the server has one
+    fn get_checksum_sidecar(&self, url: &str) -> Option<Checksum> {
+        for kind in &[HashType::Sha256, HashType::Sha1] {
+            let sidecar = format!("{}.{}", url, kind.extension());
+            trace!("GET {}", sidecar);
+            if let Ok(body) = self.get_string(&sidecar) {
+                if let Some(sum) = Checksum::parse(*kind, &body) {
+                    return Some(sum);
+                }
+            }
+        }
+        None
+    }
+}
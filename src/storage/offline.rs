@@ -0,0 +1,129 @@
+#![allow(missing_docs)]
+
+use std::fs;
+use std::vec::Vec;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use core::{CliError, LalResult, Lockfile, Version};
+use super::{Backend, Component};
+
+
+/// Read-only backend serving everything from the cache directory
+///
+/// Used with `lal --offline` when the configured backend is unreachable.
+/// Everything that would need the network fails with an error saying so.
+pub struct OfflineBackend {
+    /// Cache directory
+    pub cache: String,
+}
+
+impl OfflineBackend {
+    pub fn new(cache: &str) -> Self { OfflineBackend { cache: cache.into() } }
+
+    // Location of a cached tarball (same layout as `CachedBackend` writes)
+    fn tarball_path(&self, name: &str, version: &Version, env: &str) -> PathBuf {
+        Path::new(&self.cache)
+            .join("environments")
+            .join(env)
+            .join(name)
+            .join(version.to_string())
+            .join(format!("{}.tar.gz", name))
+    }
+}
+
+/// Artifact backend trait for `OfflineBackend`
+///
+/// Only versions that have a tarball in the cache are reported.
+impl Backend for OfflineBackend {
+    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<Version>> {
+        let dir = Path::new(&self.cache).join("environments").join(loc).join(name);
+        let mut versions = vec![];
+        if let Ok(dentries) = fs::read_dir(&dir) {
+            for entry in dentries {
+                let path = entry?;
+                if let Some(filename) = path.file_name().to_str() {
+                    if let Ok(version) = filename.parse::<Version>() {
+                        if self.tarball_path(name, &version, loc).is_file() {
+                            versions.push(version);
+                        }
+                    }
+                }
+            }
+        }
+        if versions.is_empty() {
+            return Err(CliError::OfflineUnavailable(name.into(), None, loc.into()));
+        }
+        versions.sort_by(|a, b| b.cmp(a)); // sort by version number descending
+        Ok(versions)
+    }
+
+    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<Version> {
+        Ok(self.get_versions(name, loc)?.remove(0))
+    }
+
+    fn get_component_info(
+        &self,
+        name: &str,
+        version: Option<Version>,
+        loc: &str,
+    ) -> LalResult<Component> {
+        let v = if let Some(ver) = version {
+            ver
+        } else {
+            self.get_latest_version(name, loc)?
+        };
+        let tarball = self.tarball_path(name, &v, loc);
+        if !tarball.is_file() {
+            return Err(CliError::OfflineUnavailable(name.into(), Some(v), loc.into()));
+        }
+        Ok(Component {
+            name: name.into(),
+            version: v,
+            location: tarball.to_string_lossy().into_owned(),
+        })
+    }
+
+    fn publish_artifact(&self, _name: &str, _version: &Version, _env: &str) -> LalResult<()> {
+        Err(CliError::OfflineOperation("publish".into()))
+    }
+
+    fn get_cache_dir(&self) -> String { self.cache.clone() }
+
+    fn raw_fetch(&self, src: &str, dest: &PathBuf) -> LalResult<()> {
+        debug!("raw fetch {} -> {}", src, dest.display());
+        fs::copy(src, dest)?;
+        Ok(())
+    }
+
+    /// Read the lockfile that is packed inside the cached tarball
+    fn get_component_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile> {
+        use tar::Archive;
+        use flate2::read::GzDecoder;
+        use serde_json;
+
+        let tarball = self.tarball_path(name, version, env);
+        if !tarball.is_file() {
+            let v = Some(version.clone());
+            return Err(CliError::OfflineUnavailable(name.into(), v, env.into()));
+        }
+        let mut archive = Archive::new(GzDecoder::new(fs::File::open(&tarball)?)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            // only the top level lockfile (tarballs may or may not prefix entries with ./)
+            if path.strip_prefix(".").unwrap_or(&path) != Path::new("lockfile.json") {
+                continue;
+            }
+            let mut lock_str = String::new();
+            entry.read_to_string(&mut lock_str)?;
+            return Ok(serde_json::from_str(&lock_str)?);
+        }
+        Err(CliError::MissingLockfile(name.into()))
+    }
+}
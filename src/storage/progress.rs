@@ -1,8 +1,34 @@
 use std::io;
 use std::io::{Read, Seek, SeekFrom};
 //use std::io::{Write};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
 use indicatif::{ProgressBar, ProgressStyle};
 
+// Set while an aggregate bar is drawn for parallel fetches
+static HIDE_FILE_BARS: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Hide the progress bars of individual files (parallel bars would garble the terminal)
+pub fn hide_file_bars(hide: bool) { HIDE_FILE_BARS.store(hide, Ordering::SeqCst); }
+
+/// A progress bar for a single file (hidden when `hide_file_bars` is in effect)
+pub fn file_bar(len: u64) -> ProgressBar {
+    if HIDE_FILE_BARS.load(Ordering::SeqCst) {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(len)
+    }
+}
+
+/// A spinner for a single file of unknown size (hidden like `file_bar`)
+pub fn file_spinner() -> ProgressBar {
+    if HIDE_FILE_BARS.load(Ordering::SeqCst) {
+        return ProgressBar::hidden();
+    }
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(ProgressStyle::default_spinner().template("{spinner} {bytes} ({elapsed})"));
+    pb
+}
+
 /// Wrapper around a `Read` that reports the progress made.
 ///
 /// Used to monitor slow IO readers
@@ -36,7 +62,7 @@ impl<R: Read + Seek> ProgressReader<R> {
     pub fn new(mut rdr: R) -> io::Result<ProgressReader<R>> {
         let len = rdr.seek(SeekFrom::End(0))?;
         rdr.seek(SeekFrom::Start(0))?;
-        let pb = ProgressBar::new(len);
+        let pb = file_bar(len);
         pb.set_style(ProgressStyle::default_bar()
                          .template("{bar:40.green/black} {bytes}/{total_bytes} ({eta})"));
         Ok(ProgressReader { rdr, pb })
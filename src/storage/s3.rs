@@ -0,0 +1,363 @@
+#![allow(missing_docs)]
+
+use std::vec::Vec;
+use std::io::Read;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, UTC};
+use serde_json;
+use regex::Regex;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use hyper::{self, Url};
+use hyper::method::Method;
+use hyper::header::{Authorization, ByteRangeSpec, Headers, Host, Range};
+use hyper::client::Response;
+use hyper::status::StatusCode;
+
+use core::{CliError, LalResult, Lockfile, Version};
+use super::checksum::{Checksum, HashType, to_hex, verify_download};
+use super::network::NetworkOptions;
+
+
+/// S3 access keys used for SigV4 signing
+#[derive(Serialize, Deserialize, Clone)]
+pub struct S3Credentials {
+    /// Access key id
+    pub access_key: String,
+    /// Secret access key
+    pub secret_key: String,
+}
+
+/// S3 compatible object storage locations (AWS S3 or MinIO)
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct S3Config {
+    /// Endpoint of the S3 api (e.g. `http://localhost:9000` for a MinIO)
+    pub endpoint: String,
+    /// Bucket containing the `env` tree
+    pub bucket: String,
+    /// Region used in the request signature
+    pub region: String,
+    /// Optional credentials (anonymous requests are made without them)
+    pub credentials: Option<S3Credentials>,
+    /// Timeouts and retries for requests
+    #[serde(default)]
+    pub network: NetworkOptions,
+}
+
+// aws extra headers
+header! {(XAmzDate, "x-amz-date") => [String]}
+header! {(XAmzContentSha256, "x-amz-content-sha256") => [String]}
+
+fn sha256_hex(data: &[u8]) -> LalResult<String> {
+    let digest = hash(MessageDigest::sha256(), data)
+        .map_err(|e| CliError::BackendFailure(format!("sha256 failed: {}", e)))?;
+    Ok(to_hex(&digest))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> LalResult<Vec<u8>> {
+    let hmac = || -> Result<Vec<u8>, ::openssl::error::ErrorStack> {
+        let pkey = PKey::hmac(key)?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+        signer.update(data)?;
+        signer.finish()
+    };
+    hmac().map_err(|e| CliError::BackendFailure(format!("hmac failed: {}", e)))
+}
+
+/// Percent encoding as required by the SigV4 canonical request
+fn uri_encode(input: &str, encode_slash: bool) -> String {
+    let mut res = String::new();
+    for b in input.bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => {
+                res.push(b as char)
+            }
+            b'/' if !encode_slash => res.push('/'),
+            _ => res.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    res
+}
+
+/// Parse a `ListObjectsV2` response into versions
+///
+/// Only the folder names under the queried prefix (`CommonPrefixes`) are considered.
+fn parse_versions(body: &str, prefix: &str) -> Vec<Version> {
+    let re = Regex::new(r"<CommonPrefixes>\s*<Prefix>([^<]*)</Prefix>").unwrap();
+    re.captures_iter(body)
+        .filter_map(|c| c.at(1))
+        .filter_map(|p| p.trim_left_matches(prefix).trim_matches('/').parse().ok())
+        .collect()
+}
+
+use super::{Backend, Component};
+
+/// Artifact storage in an S3 compatible bucket
+pub struct S3Backend {
+    /// S3 config and credentials
+    pub config: S3Config,
+    /// Cache directory
+    pub cache: String,
+}
+
+impl S3Backend {
+    pub fn new(cfg: &S3Config, cache: &str) -> Self {
+        S3Backend {
+            config: cfg.clone(),
+            cache: cache.into(),
+        }
+    }
+
+    // Path style url to an object (works with MinIO without dns setup)
+    fn object_url(&self, key: &str) -> String {
+        format!("{}/{}/{}", self.config.endpoint, self.config.bucket, uri_encode(key, false))
+    }
+
+    /// Create the headers for a SigV4 signed request made at `now`
+    ///
+    /// `url` must already be in its canonical encoded form with sorted query parameters.
+    pub fn signed_headers(
+        &self,
+        method: &str,
+        url: &str,
+        payload: &[u8],
+        now: DateTime<UTC>,
+    ) -> LalResult<Headers> {
+        let parsed = Url::parse(url)
+            .map_err(|e| CliError::BackendFailure(format!("invalid s3 url {}: {}", url, e)))?;
+        let host = Host {
+            hostname: parsed.host_str().unwrap_or("").into(),
+            port: parsed.port(),
+        };
+        let payload_hash = sha256_hex(payload)?;
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+
+        let mut headers = Headers::new();
+        headers.set(XAmzContentSha256(payload_hash.clone()));
+        headers.set(XAmzDate(amz_date.clone()));
+
+        let creds = match self.config.credentials {
+            Some(ref c) => c,
+            None => {
+                headers.set(host);
+                return Ok(headers); // anonymous request
+            }
+        };
+
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let signed = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!("{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\n\
+                                         x-amz-date:{}\n\n{}\n{}",
+                                        method,
+                                        parsed.path(),
+                                        parsed.query().unwrap_or(""),
+                                        host,
+                                        payload_hash,
+                                        amz_date,
+                                        signed,
+                                        payload_hash);
+        trace!("Canonical request:\n{}", canonical_request);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                                     amz_date,
+                                     scope,
+                                     sha256_hex(canonical_request.as_bytes())?);
+
+        let secret = format!("AWS4{}", creds.secret_key);
+        let k_date = hmac_sha256(secret.as_bytes(), date.as_bytes())?;
+        let k_region = hmac_sha256(&k_date, self.config.region.as_bytes())?;
+        let k_service = hmac_sha256(&k_region, b"s3")?;
+        let k_signing = hmac_sha256(&k_service, b"aws4_request")?;
+        let signature = to_hex(&hmac_sha256(&k_signing, string_to_sign.as_bytes())?);
+
+        headers.set(host);
+        headers.set(Authorization(format!("AWS4-HMAC-SHA256 Credential={}/{}, \
+                                           SignedHeaders={}, Signature={}",
+                                          creds.access_key,
+                                          scope,
+                                          signed,
+                                          signature)));
+        Ok(headers)
+    }
+
+    fn send(&self, method: Method, url: &str, body: &[u8]) -> LalResult<Response> {
+        self.send_from(method, url, body, 0)
+    }
+
+    // send a request for the object from byte `offset` onwards (when non-zero)
+    fn send_from(
+        &self,
+        method: Method,
+        url: &str,
+        body: &[u8],
+        offset: u64,
+    ) -> LalResult<Response> {
+        let client = self.config.network.client();
+        let mut headers = self.signed_headers(method.as_ref(), url, body, UTC::now())?;
+        if offset > 0 {
+            headers.set(Range::Bytes(vec![ByteRangeSpec::AllFrom(offset)]));
+        }
+        debug!("{} {}", method, url);
+        let req = client.request(method.clone(), url).headers(headers);
+        let resp = if method == Method::Put { req.body(body).send()? } else { req.send()? };
+        Ok(resp)
+    }
+
+    fn upload_object(&self, key: &str, body: &[u8]) -> LalResult<()> {
+        if self.config.credentials.is_none() {
+            return Err(CliError::MissingBackendCredentials);
+        }
+        let url = self.object_url(key);
+        info!("PUT {}", url);
+        let resp = self.send(Method::Put, &url, body)?;
+        debug!("resp={:?}", resp);
+        let respstr = format!("{} from PUT {}", resp.status, url);
+        if resp.status != StatusCode::Ok {
+            return Err(CliError::UploadFailure(respstr));
+        }
+        debug!("{}", respstr);
+        Ok(())
+    }
+}
+
+/// Artifact backend trait for `S3Backend`
+///
+/// This is intended to be used by the caching trait `CachedBackend`, but for
+/// specific low-level use cases, these methods can be used directly.
+impl Backend for S3Backend {
+    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<Version>> {
+        let prefix = format!("env/{}/{}/", loc, name);
+        let next_re = Regex::new(r"<NextContinuationToken>([^<]*)</NextContinuationToken>")
+            .unwrap();
+        let mut builds = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            // query parameters must be sorted for the signature
+            let mut query = vec![];
+            if let Some(ref t) = token {
+                query.push(format!("continuation-token={}", uri_encode(t, true)));
+            }
+            query.push("delimiter=%2F".to_string());
+            query.push("list-type=2".to_string());
+            query.push(format!("prefix={}", uri_encode(&prefix, true)));
+            let url = format!("{}/{}?{}",
+                              self.config.endpoint,
+                              self.config.bucket,
+                              query.join("&"));
+
+            let mut resp = self.send(Method::Get, &url, b"")?;
+            if resp.status != hyper::Ok {
+                warn!("Failed to GET {}: {}", url, resp.status);
+                return Err(CliError::BackendFailure("No version information found in bucket"
+                                                        .into()));
+            }
+            let mut body = String::new();
+            resp.read_to_string(&mut body)?;
+            trace!("Got body {}", body);
+            builds.extend(parse_versions(&body, &prefix));
+
+            token = next_re.captures(&body).and_then(|c| c.at(1)).map(String::from);
+            if !body.contains("<IsTruncated>true</IsTruncated>") || token.is_none() {
+                break;
+            }
+        }
+        builds.sort_by(|a, b| b.cmp(a)); // sort by version descending
+        Ok(builds)
+    }
+
+    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<Version> {
+        if let Some(latest) = self.get_versions(name, loc)?.into_iter().max() {
+            return Ok(latest);
+        }
+        Err(CliError::BackendFailure("No version information found in bucket".into()))
+    }
+
+    fn get_component_info(
+        &self,
+        name: &str,
+        version: Option<Version>,
+        loc: &str,
+    ) -> LalResult<Component> {
+        let v = if let Some(ver) = version {
+            ver
+        } else {
+            self.get_latest_version(name, loc)?
+        };
+        let key = format!("env/{}/{}/{}/{}.tar.gz", loc, name, v, name);
+        Ok(Component {
+            name: name.into(),
+            version: v,
+            location: self.object_url(&key),
+        })
+    }
+
+    fn publish_artifact(&self, name: &str, version: &Version, env: &str) -> LalResult<()> {
+        // this fn basically assumes all the sanity checks have been performed
+        // files must exist and lockfile must be sensible
+        let artdir = Path::new("./ARTIFACT");
+        let tarball = artdir.join(format!("{}.tar.gz", name));
+        let lockfile = artdir.join("lockfile.json");
+
+        // same key layout as the artifactory env uploads
+        let prefix = format!("env/{}/", env);
+
+        let tar_key = format!("{}{}/{}/{}.tar.gz", prefix, name, version, name);
+        let mut tar_data = vec![];
+        File::open(&tarball)?.read_to_end(&mut tar_data)?;
+        self.upload_object(&tar_key, &tar_data)?;
+
+        // checksum sidecar so downloads can be verified
+        let sum = Checksum::of_file(HashType::Sha256, &tarball)?;
+        let sum_key = format!("{}.{}", tar_key, sum.kind.extension());
+        let sum_data = sum.sidecar_contents(&format!("{}.tar.gz", name));
+        self.upload_object(&sum_key, sum_data.as_bytes())?;
+
+        let lf_key = format!("{}{}/{}/lockfile.json", prefix, name, version);
+        let mut lf_data = vec![];
+        File::open(lockfile)?.read_to_end(&mut lf_data)?;
+        self.upload_object(&lf_key, &lf_data)?;
+        Ok(())
+    }
+
+    fn get_cache_dir(&self) -> String { self.cache.clone() }
+
+    fn raw_fetch(&self, url: &str, dest: &PathBuf) -> LalResult<()> {
+        let network = &self.config.network;
+        let mut expected = network.download_with(url, dest, |offset| {
+            self.send_from(Method::Get, url, b"", offset)
+        })?;
+
+        // otherwise verify against the sidecar written by publish_artifact
+        if expected.is_none() {
+            let sum_url = format!("{}.{}", url, HashType::Sha256.extension());
+            if let Ok(mut sum_resp) = self.send(Method::Get, &sum_url, b"") {
+                let mut body = String::new();
+                if sum_resp.status == hyper::Ok && sum_resp.read_to_string(&mut body).is_ok() {
+                    expected = Checksum::parse(HashType::Sha256, &body);
+                }
+            }
+        }
+        verify_download(expected, dest, url)
+    }
+
+    fn get_component_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile> {
+        let key = format!("env/{}/{}/{}/lockfile.json", env, name, version);
+        let url = self.object_url(&key);
+        trace!("Fetching lockfile from {}", url);
+        let mut resp = self.send(Method::Get, &url, b"")?;
+        if resp.status != hyper::Ok {
+            return Err(CliError::BackendFailure(format!("GET request with {}", resp.status)));
+        }
+        let mut body = String::new();
+        resp.read_to_string(&mut body)?;
+        Ok(serde_json::from_str(&body)?)
+    }
+}
@@ -1,12 +1,14 @@
 use std::path::PathBuf;
 
-use core::LalResult;
-use super::{ArtifactoryConfig, LocalConfig};
+use core::{LalResult, Lockfile, Version};
+use super::{ArtifactoryConfig, LocalConfig, HttpConfig, S3Config, ChainConfig};
+use super::{ArtifactoryBackend, LocalBackend, HttpBackend, S3Backend, ChainBackend};
 
 /// An enum struct for the currently configured `Backend`
 ///
 /// Any other implementations should be imported and listed here.
-/// Currently only artifactory is supported.
+/// Currently artifactory, local, plain http and s3 storage is supported,
+/// as well as a chain of these falling back to each other.
 #[derive(Serialize, Deserialize, Clone)]
 pub enum BackendConfiguration {
     /// Config for the `ArtifactoryBackend`
@@ -16,6 +18,18 @@ pub enum BackendConfiguration {
     /// Config for the `LocalBackend`
     #[serde(rename = "local")]
     Local(LocalConfig),
+
+    /// Config for the `HttpBackend`
+    #[serde(rename = "http")]
+    Http(HttpConfig),
+
+    /// Config for the `S3Backend`
+    #[serde(rename = "s3")]
+    S3(S3Config),
+
+    /// Config for the `ChainBackend`
+    #[serde(rename = "chain")]
+    Chain(ChainConfig),
 }
 
 /// Artifactory is the default backend
@@ -23,6 +37,21 @@ impl Default for BackendConfiguration {
     fn default() -> Self { BackendConfiguration::Artifactory(ArtifactoryConfig::default()) }
 }
 
+impl BackendConfiguration {
+    /// Create the configured `Backend` using a given cache directory
+    pub fn to_backend(&self, cache: &str) -> Box<Backend> {
+        match *self {
+            BackendConfiguration::Artifactory(ref cfg) => {
+                Box::new(ArtifactoryBackend::new(cfg, cache))
+            }
+            BackendConfiguration::Local(ref cfg) => Box::new(LocalBackend::new(cfg, cache)),
+            BackendConfiguration::Http(ref cfg) => Box::new(HttpBackend::new(cfg, cache)),
+            BackendConfiguration::S3(ref cfg) => Box::new(S3Backend::new(cfg, cache)),
+            BackendConfiguration::Chain(ref cfg) => Box::new(ChainBackend::new(cfg, cache)),
+        }
+    }
+}
+
 
 /// The basic definition of a component as it exists online
 ///
@@ -30,8 +59,8 @@ impl Default for BackendConfiguration {
 pub struct Component {
     /// Name of the component
     pub name: String,
-    /// Version number
-    pub version: u32,
+    /// Version
+    pub version: Version,
     /// The raw location of the component at the specified version number
     ///
     /// No restriction on how this information is encoded, but it must work with `raw_fetch`
@@ -43,27 +72,44 @@ pub struct Component {
 /// We are not really relying on Artifactory specific quirks in our default usage
 /// so that in case it fails it can be switched over.
 /// We do rely on there being a basic API that can implement this trait though.
-pub trait Backend {
+///
+/// Backends are shared between the threads of parallel fetches, so must be `Sync`.
+pub trait Backend: Sync {
     /// Get a list of versions for a component in descending order
-    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<u32>>;
+    fn get_versions(&self, name: &str, loc: &str) -> LalResult<Vec<Version>>;
     /// Get the latest version of a component
-    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<u32>;
+    fn get_latest_version(&self, name: &str, loc: &str) -> LalResult<Version>;
 
     /// Get the version and location information of a component
     ///
     /// If no version is given, figure out what latest is
-    fn get_component_info(&self, name: &str, ver: Option<u32>, loc: &str) -> LalResult<Component>;
+    fn get_component_info(
+        &self,
+        name: &str,
+        ver: Option<Version>,
+        loc: &str,
+    ) -> LalResult<Component>;
 
     /// Publish a release build's ARTIFACT to a specific location
     ///
     /// This will publish everything inside the ARTIFACT dir created by `lal build -r`
-    fn publish_artifact(&self, name: &str, version: u32, env: &str) -> LalResult<()>;
+    fn publish_artifact(&self, name: &str, version: &Version, env: &str) -> LalResult<()>;
 
     /// Raw fetch of location to a destination
     ///
     /// location can be a HTTPS url / a system path / etc (depending on the backend)
     fn raw_fetch(&self, location: &str, dest: &PathBuf) -> LalResult<()>;
 
+    /// Get the lockfile published alongside a component without its tarball
+    ///
+    /// Every backend publishes `lockfile.json` next to the tarball.
+    fn get_component_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile>;
+
     /// Return the base directory to be used to dump cached downloads
     ///
     /// This has to be in here for `CachedBackend` to have a straight dependency
@@ -79,16 +125,24 @@ pub trait CachedBackend {
         &self,
         name: &str,
         environments: Vec<String>,
-    ) -> LalResult<Vec<u32>>;
+    ) -> LalResult<Vec<Version>>;
 
     /// Retrieve the location to a cached published component (downloading if necessary)
     fn retrieve_published_component(
         &self,
         name: &str,
-        version: Option<u32>,
+        version: Option<Version>,
         env: &str,
     ) -> LalResult<(PathBuf, Component)>;
 
+    /// Retrieve the lockfile of a published component (fetching it if necessary)
+    fn retrieve_published_lockfile(
+        &self,
+        name: &str,
+        version: &Version,
+        env: &str,
+    ) -> LalResult<Lockfile>;
+
     /// Retrieve the location to a stashed component
     fn retrieve_stashed_component(&self, name: &str, code: &str) -> LalResult<PathBuf>;
 
@@ -96,10 +150,20 @@ pub trait CachedBackend {
     fn unpack_published_component(
         &self,
         name: &str,
-        version: Option<u32>,
+        version: Option<Version>,
         env: &str,
     ) -> LalResult<Component>;
 
+    /// Retrieve and unpack several published components into INPUT in parallel
+    ///
+    /// Results are returned in the same order as the requested components.
+    fn unpack_published_components(
+        &self,
+        components: &[(String, Version)],
+        env: &str,
+        jobs: usize,
+    ) -> Vec<LalResult<Component>>;
+
     /// Retrieve and unpack a stashed component to INPUT
     fn unpack_stashed_component(&self, name: &str, code: &str) -> LalResult<()>;
 
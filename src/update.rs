@@ -1,5 +1,28 @@
-use storage::CachedBackend;
-use super::{LalResult, Manifest, CliError};
+use std::collections::BTreeMap;
+
+use storage::{Backend, CachedBackend};
+use super::{LalResult, Manifest, CliError, Version, VersionReq, Resolution, input};
+use fetch::installed_resolution;
+use resolve::check_published;
+
+/// Resolve a version requirement from the manifest
+///
+/// Constraints resolve to the highest matching version that is available
+/// in all the `supportedEnvironments`.
+pub fn resolve_version<T: CachedBackend + ?Sized>(
+    backend: &T,
+    manifest: &Manifest,
+    name: &str,
+    req: &VersionReq,
+) -> LalResult<Version> {
+    if let Some(v) = req.exact() {
+        return Ok(v.clone());
+    }
+    let versions =
+        backend.get_latest_supported_versions(name, manifest.supportedEnvironments.clone())?;
+    req.best_match(&versions)
+        .ok_or_else(|| CliError::NoMatchingVersion(name.into(), req.to_string()))
+}
 
 /// Update specific dependencies outside the manifest
 ///
@@ -9,35 +32,39 @@ use super::{LalResult, Manifest, CliError};
 /// If installation was successful, the fetched tarballs are unpacked into `./INPUT`.
 /// If one `save` or `savedev` was set, the fetched versions are also updated in the
 /// manifest. This provides an easy way to not have to deal with strict JSON manually.
-pub fn update<T: CachedBackend + ?Sized>(
+///
+/// When saving, components with a version constraint in the manifest are updated to
+/// the highest version satisfying it, and the constraint is kept.
+/// Saved components are also recorded in `.lal/lock.json`.
+///
+/// The tree that would end up in `./INPUT` is checked from the published lockfiles
+/// first, and nothing is downloaded if it has version conflicts, cycles or
+/// environment mismatches.
+/// Published components are downloaded using up to `jobs` parallel downloads.
+pub fn update<T: CachedBackend + Backend + ?Sized>(
     manifest: &Manifest,
     backend: &T,
     components: Vec<String>,
     save: bool,
     savedev: bool,
     env: &str,
+    jobs: usize,
 ) -> LalResult<()> {
     debug!("Update specific deps: {:?}", components);
 
     let mut error = None;
-    let mut updated = Vec::with_capacity(components.len());
+    let mut published = Vec::with_capacity(components.len());
     for comp in &components {
-        info!("Fetch {} {}", env, comp);
         if comp.contains('=') {
             let pair: Vec<&str> = comp.split('=').collect();
-            if let Ok(n) = pair[1].parse::<u32>() {
+            if let Ok(n) = pair[1].parse::<Version>() {
                 if pair[0].to_lowercase() != pair[0] {
                     return Err(CliError::InvalidComponentName(pair[0].into()));
                 }
-                // standard fetch with an integer version
-                match backend.unpack_published_component(pair[0], Some(n), env) {
-                    Ok(c) => updated.push(c),
-                    Err(e) => {
-                        warn!("Failed to update {} ({})", pair[0], e);
-                        error = Some(e);
-                    }
-                }
+                // standard fetch with a published version
+                published.push((pair[0].to_string(), n));
             } else {
+                info!("Fetch {} {}", env, comp);
                 // fetch from stash - this does not go into `updated` it it succeeds
                 // because we wont and cannot save stashed versions in the manifest
                 let _ = backend.unpack_stashed_component(pair[0], pair[1]).map_err(|e| {
@@ -49,7 +76,21 @@ pub fn update<T: CachedBackend + ?Sized>(
             if &comp.to_lowercase() != comp {
                 return Err(CliError::InvalidComponentName(comp.clone()));
             }
-            // fetch without a specific version (latest)
+            // fetch without a specific version (latest, or latest matching when saving)
+            let saved = if save {
+                manifest.dependencies.get(comp)
+            } else if savedev {
+                manifest.devDependencies.get(comp)
+            } else {
+                None
+            };
+            if let Some(req) = saved {
+                if req.exact().is_none() {
+                    let ver = resolve_version(backend, manifest, comp, req)?;
+                    published.push((comp.clone(), ver));
+                    continue;
+                }
+            }
 
             // First, since this potentially goes in the manifest
             // make sure the version is found for all supported environments:
@@ -58,14 +99,33 @@ pub fn update<T: CachedBackend + ?Sized>(
                 .into_iter()
                 .max()
                 .ok_or(CliError::NoIntersectedVersion(comp.clone()))?;
-            info!("Fetch {} {}={}", env, comp, ver);
+            published.push((comp.clone(), ver));
+        }
+    }
 
-            match backend.unpack_published_component(comp, Some(ver), env) {
-                Ok(c) => updated.push(c),
-                Err(e) => {
-                    warn!("Failed to update {} ({})", &comp, e);
-                    error = Some(e);
-                }
+    // refuse to leave an inconsistent tree in INPUT before downloading anything
+    if !published.is_empty() {
+        let mut resulting = BTreeMap::new();
+        for (name, version) in input::analyze()? {
+            // stashed components are not published, so cannot be checked
+            if let Ok(v) = version.parse::<Version>() {
+                resulting.insert(name, v);
+            }
+        }
+        for &(ref name, ref version) in &published {
+            resulting.insert(name.clone(), version.clone());
+        }
+        check_published(backend, &manifest.name, &resulting, env)?;
+    }
+
+    let mut updated = Vec::with_capacity(published.len());
+    let results = backend.unpack_published_components(&published, env, jobs);
+    for (&(ref name, _), res) in published.iter().zip(results) {
+        match res {
+            Ok(c) => updated.push(c),
+            Err(e) => {
+                warn!("Failed to update {} ({})", name, e);
+                error = Some(e);
             }
         }
     }
@@ -76,23 +136,34 @@ pub fn update<T: CachedBackend + ?Sized>(
     // Update manifest if saving in any way
     if save || savedev {
         let mut mf = manifest.clone();
+        let mut resolution = Resolution::read()?;
+        let cache = backend.get_cache_dir();
         // find reference to correct list
         let mut hmap = if save { mf.dependencies.clone() } else { mf.devDependencies.clone() };
         for c in &updated {
             debug!("Successfully updated {} at version {}", &c.name, c.version);
-            if hmap.contains_key(&c.name) {
-                let val = hmap.get_mut(&c.name).unwrap();
-                if c.version < *val {
-                    warn!("Downgrading {} from {} to {}", c.name, *val, c.version);
-                } else if c.version > *val {
-                    info!("Upgrading {} from {} to {}", c.name, *val, c.version);
-                } else {
-                    info!("Maintaining {} at version {}", c.name, c.version);
+            let dep = installed_resolution(&cache, &c.name, env)?;
+            resolution.dependencies.insert(c.name.clone(), dep);
+            if let Some(req) = hmap.get(&c.name).cloned() {
+                if req.exact().is_none() {
+                    if req.matches(&c.version) {
+                        info!("Resolved {} {} to {}", c.name, req, c.version);
+                        continue;
+                    }
+                    warn!("Replacing constraint {} on {} with {}", req, c.name, c.version);
                 }
-                *val = c.version;
-            } else {
-                hmap.insert(c.name.clone(), c.version);
             }
+            match hmap.get(&c.name).and_then(|req| req.exact()) {
+                Some(v) if c.version < *v => {
+                    warn!("Downgrading {} from {} to {}", c.name, v, c.version)
+                }
+                Some(v) if c.version > *v => {
+                    info!("Upgrading {} from {} to {}", c.name, v, c.version)
+                }
+                Some(_) => info!("Maintaining {} at version {}", c.name, c.version),
+                None => {}
+            }
+            hmap.insert(c.name.clone(), c.version.clone().into());
         }
         if save {
             mf.dependencies = hmap;
@@ -100,6 +171,7 @@ pub fn update<T: CachedBackend + ?Sized>(
             mf.devDependencies = hmap;
         }
         mf.write()?;
+        resolution.write()?;
     }
     Ok(())
 }
@@ -109,17 +181,18 @@ pub fn update<T: CachedBackend + ?Sized>(
 /// This will pass all dependencies or devDependencies to update.
 /// If the save flag is set, then the manifest will be updated correctly.
 /// I.e. dev updates will update only the dev portions of the manifest.
-pub fn update_all<T: CachedBackend + ?Sized>(
+pub fn update_all<T: CachedBackend + Backend + ?Sized>(
     manifest: &Manifest,
     backend: &T,
     save: bool,
     dev: bool,
     env: &str,
+    jobs: usize,
 ) -> LalResult<()> {
     let deps: Vec<String> = if dev {
         manifest.devDependencies.keys().cloned().collect()
     } else {
         manifest.dependencies.keys().cloned().collect()
     };
-    update(manifest, backend, deps, save && !dev, save && dev, env)
+    update(manifest, backend, deps, save && !dev, save && dev, env, jobs)
 }
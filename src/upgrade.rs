@@ -112,6 +112,9 @@ fn upgrade_exe(latest: &LatestLal, exe: &ExeInfo) -> LalResult<()> {
     }
     // 2. make sure we can download the tarball before starting
     let tar_dest = prefix.join("lal.tar.gz");
+    if tar_dest.is_file() {
+        fs::remove_file(&tar_dest)?; // would otherwise be resumed
+    }
     info!("Downloading tarball to {}", tar_dest.display());
     http_download_to_path(&latest.url, &tar_dest)?;
     info!("Backing up {} to {}", exe.path, old_file.display());
@@ -49,3 +49,23 @@ pub fn verify(m: &Manifest, env: &str, simple: bool) -> LalResult<()> {
     info!("Dependencies fully verified");
     Ok(())
 }
+
+/// Warns when `./INPUT` was built with different images of the same environment
+///
+/// Mutable tags like `latest` can point at different images over time, so dependencies
+/// built in the same environment can still have been built with different images.
+/// This compares the image digests recorded in the lockfiles of `./INPUT`.
+pub fn verify_digests(env: &str) -> LalResult<()> {
+    let lf = Lockfile::default().populate_from_input()?;
+    let digests = input::find_container_digests(&lf, env);
+    if digests.len() > 1 {
+        warn!("Dependencies were built with {} different images of the {} environment:",
+              digests.len(),
+              env);
+        for (digest, names) in digests {
+            let names: Vec<String> = names.into_iter().collect();
+            warn!("- {}: {}", digest, names.join(", "));
+        }
+    }
+    Ok(())
+}
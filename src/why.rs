@@ -0,0 +1,111 @@
+use ansi_term::Colour;
+use serde_json;
+
+use storage::CachedBackend;
+use super::{LalResult, CliError, Manifest, Version};
+use propagate::manifest_tree;
+use resolve::{chains, format_chain, Chain};
+
+/// A chain of dependencies from the root manifest that pulls in a component
+#[derive(Serialize)]
+pub struct WhyChain {
+    /// Components along the chain, ending with the component asked about
+    pub hops: Chain,
+    /// Whether the component is used at a version the manifest does not allow
+    pub differs: bool,
+}
+
+/// Every reason a component is part of the dependency tree
+#[derive(Serialize)]
+pub struct WhyResult {
+    /// Name of the root component
+    pub root: String,
+    /// Component asked about
+    pub component: String,
+    /// Version requirement for the component in the manifest (if it is a direct dependency)
+    pub requirement: Option<String>,
+    /// Chains leading to the component
+    pub chains: Vec<WhyChain>,
+}
+
+/// Find every chain from the root manifest to a component
+///
+/// With a `component=version`, only chains ending at that version are included.
+pub fn compute<T: CachedBackend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    env: &str,
+    component: &str,
+) -> LalResult<WhyResult> {
+    let (name, version) = if component.contains('=') {
+        let pair: Vec<&str> = component.split('=').collect();
+        (pair[0], Some(pair[1]))
+    } else {
+        (component, None)
+    };
+    let req = manifest.all_dependencies().get(name).cloned();
+
+    let lf = manifest_tree(manifest, backend, env)?;
+    let mut res = vec![];
+    for chain in chains(&lf) {
+        let differs = {
+            let hop = &chain[chain.len() - 1];
+            if hop.name != name || version.map_or(false, |v| v != hop.version) {
+                continue;
+            }
+            match (req.as_ref(), hop.version.parse::<Version>()) {
+                (Some(r), Ok(v)) => !r.matches(&v),
+                (Some(_), Err(_)) => true, // stashed versions never match
+                (None, _) => false,
+            }
+        };
+        res.push(WhyChain {
+            hops: chain,
+            differs: differs,
+        });
+    }
+    if res.is_empty() {
+        return Err(CliError::NotInDependencyTree(component.into()));
+    }
+    Ok(WhyResult {
+        root: manifest.name.clone(),
+        component: name.into(),
+        requirement: req.map(|r| r.to_string()),
+        chains: res,
+    })
+}
+
+/// Prints every chain of dependencies that pulls a component into the tree
+///
+/// Each chain starts at the current manifest and shows the version and environment
+/// at every hop. Chains using a version of the component that the manifest does not
+/// allow are highlighted.
+pub fn why<T: CachedBackend + ?Sized>(
+    manifest: &Manifest,
+    backend: &T,
+    env: &str,
+    component: &str,
+    json_output: bool,
+) -> LalResult<()> {
+    let result = compute(manifest, backend, env, component)?;
+
+    if json_output {
+        let encoded = serde_json::to_string_pretty(&result)?;
+        println!("{}", encoded);
+    } else {
+        if let Some(ref req) = result.requirement {
+            println!("{} requires {} at {}", result.root, result.component, req);
+        }
+        for chain in &result.chains {
+            let line = format_chain(&result.root, &chain.hops);
+            if chain.differs {
+                println!("{} {}",
+                         Colour::Yellow.paint(line),
+                         Colour::Red.paint("(differs from manifest)"));
+            } else {
+                println!("{}", line);
+            }
+        }
+    }
+    Ok(())
+}